@@ -0,0 +1,65 @@
+use arboard::Clipboard;
+use parking_lot::Mutex;
+use tauri::State;
+
+use crate::error::CommandError;
+use crate::logger::redact_secrets;
+
+/// System clipboard handle, opened on first use.
+///
+/// Kept alive for the app's lifetime because on X11 and Wayland the owning
+/// handle must stay open for copied text to remain pasteable.
+#[derive(Default)]
+pub struct ClipboardState {
+    clipboard: Mutex<Option<Clipboard>>,
+}
+
+impl ClipboardState {
+    fn with_clipboard<T>(
+        &self,
+        f: impl FnOnce(&mut Clipboard) -> Result<T, arboard::Error>,
+    ) -> Result<T, CommandError> {
+        let mut guard = self.clipboard.lock();
+        let clipboard = match guard.as_mut() {
+            Some(clipboard) => clipboard,
+            None => guard.insert(Clipboard::new().map_err(|e| {
+                CommandError::NotSupported(format!("Clipboard is unavailable: {}", e))
+            })?),
+        };
+        f(clipboard).map_err(|e| match e {
+            arboard::Error::ClipboardNotSupported | arboard::Error::ClipboardOccupied => {
+                CommandError::NotSupported(format!("Clipboard is unavailable: {}", e))
+            }
+            other => CommandError::Io(format!("Clipboard error: {}", other)),
+        })
+    }
+
+    /// Replace the clipboard contents with `text`.
+    pub(crate) fn set_text(&self, text: String) -> Result<(), CommandError> {
+        self.with_clipboard(|clipboard| clipboard.set_text(text))
+    }
+}
+
+/// Place text on the system clipboard.
+///
+/// With `redact`, secrets are replaced using the same rules as the audit
+/// log before the text leaves the app.
+#[tauri::command]
+pub fn copy_to_clipboard(
+    state: State<'_, ClipboardState>,
+    text: String,
+    redact: bool,
+) -> Result<(), CommandError> {
+    let text = if redact { redact_secrets(&text) } else { text };
+    state.set_text(text)
+}
+
+/// Read text from the system clipboard. Returns an empty string if the
+/// clipboard holds no text.
+#[tauri::command]
+pub fn read_clipboard(state: State<'_, ClipboardState>) -> Result<String, CommandError> {
+    state.with_clipboard(|clipboard| match clipboard.get_text() {
+        Err(arboard::Error::ContentNotAvailable) => Ok(String::new()),
+        other => other,
+    })
+}
@@ -0,0 +1,67 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+/// Directory holding the app's saved settings and, by default, its logs.
+pub(crate) fn app_data_dir() -> PathBuf {
+    let base = dirs::data_local_dir().unwrap_or_else(|| PathBuf::from("/tmp"));
+    base.join("com.aiterminal.app")
+}
+
+/// Path of the settings file `name` in `app_data_dir`.
+pub(crate) fn config_path(name: &str) -> PathBuf {
+    app_data_dir().join(name)
+}
+
+/// Replace the file at `path` with `contents`, creating its directory.
+///
+/// Settings can hold credentials or show what a deployment restricts, so
+/// the file is private to the user (mode 0600) before anything is written
+/// to it.
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    // `mode` only applies to new files.
+    #[cfg(unix)]
+    {
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_settings_privately() {
+        let dir = std::env::temp_dir().join(format!("config-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("nested").join("settings.json");
+
+        write_private(&path, b"{\"a\":1}").unwrap();
+        write_private(&path, b"{}").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{}");
+
+        #[cfg(unix)]
+        {
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
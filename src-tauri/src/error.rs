@@ -0,0 +1,60 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Error returned from Tauri commands.
+///
+/// Serialized as `{ "kind": "...", "message": "..." }` so the frontend can
+/// branch on `kind` and still show `message` to the user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum CommandError {
+    /// No session exists with the given ID.
+    SessionNotFound(String),
+    /// An argument was malformed or not allowed.
+    InvalidInput(String),
+    /// The operation doesn't apply to the session's current state.
+    InvalidState(String),
+    /// The OS refused access.
+    PermissionDenied(String),
+    /// Reading or writing failed.
+    Io(String),
+    /// A PTY or child process couldn't be created.
+    Spawn(String),
+    /// The operation isn't available on this platform or shell.
+    NotSupported(String),
+}
+
+impl CommandError {
+    pub fn session_not_found(session_id: &str) -> Self {
+        Self::SessionNotFound(format!("Session {} not found", session_id))
+    }
+
+    /// Wrap an I/O error, preserving permission failures as their own kind.
+    pub fn io(context: &str, err: std::io::Error) -> Self {
+        let message = format!("{}: {}", context, err);
+        match err.kind() {
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied(message),
+            _ => Self::Io(message),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::SessionNotFound(m)
+            | Self::InvalidInput(m)
+            | Self::InvalidState(m)
+            | Self::PermissionDenied(m)
+            | Self::Io(m)
+            | Self::Spawn(m)
+            | Self::NotSupported(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for CommandError {}
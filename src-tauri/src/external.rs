@@ -1,5 +1,11 @@
+use parking_lot::Mutex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
 use url::Url;
 
+use crate::config;
+
 const ALLOWED_HOSTS: &[&str] = &[
     "apercallc.com",
     "www.apercallc.com",
@@ -7,20 +13,137 @@ const ALLOWED_HOSTS: &[&str] = &[
     "www.github.com",
 ];
 
-fn is_allowed_host(host: &str) -> bool {
+/// User-configured hosts, merged with the compile-time `ALLOWED_HOSTS`.
+pub struct AllowedHosts {
+    hosts: Mutex<Vec<String>>,
+}
+
+impl AllowedHosts {
+    /// Load the persisted user allowlist, ignoring invalid or missing data.
+    pub fn load() -> Self {
+        let hosts = fs::read_to_string(get_allowed_hosts_path())
+            .ok()
+            .and_then(|json| serde_json::from_str::<Vec<String>>(&json).ok())
+            .map(|hosts| {
+                hosts
+                    .into_iter()
+                    .filter_map(|h| normalize_host(&h).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            hosts: Mutex::new(hosts),
+        }
+    }
+}
+
+impl Default for AllowedHosts {
+    fn default() -> Self {
+        Self::load()
+    }
+}
+
+fn get_allowed_hosts_path() -> PathBuf {
+    config::config_path("allowed_hosts.json")
+}
+
+/// Validate that `host` is a plausible bare hostname, or a `*.domain`
+/// wildcard, and lowercase it.
+///
+/// Wildcards need at least two labels after `*.`, so `*.com` is rejected.
+fn normalize_host(host: &str) -> Result<String, String> {
+    let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+    if let Some(base) = host.strip_prefix("*.") {
+        let base = normalize_host(base)?;
+        if base.starts_with("*.") {
+            return Err(format!("Invalid wildcard host: {}", host));
+        }
+        return Ok(format!("*.{}", base));
+    }
+
+    if host.is_empty() || host.len() > 253 {
+        return Err(format!("Invalid host: {:?}", host));
+    }
+    if host.contains("://") || host.contains('/') || host.contains(':') {
+        return Err(format!(
+            "Host must not include a scheme, port or path: {}",
+            host
+        ));
+    }
+    if host.contains('*') {
+        return Err(format!(
+            "Wildcards are only supported as a leading \"*.\": {}",
+            host
+        ));
+    }
+    if !host.contains('.') {
+        return Err(format!("Host must be a fully qualified name: {}", host));
+    }
+
+    let labels_ok = host.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+    if !labels_ok {
+        return Err(format!("Invalid host: {}", host));
+    }
+
+    Ok(host)
+}
+
+/// Whether `host` equals `pattern`, or is a subdomain (at any depth) of a
+/// `*.domain` pattern. A wildcard doesn't match the bare domain itself.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix('*') {
+        Some(suffix) => host.len() > suffix.len() && host.ends_with(suffix),
+        None => pattern == host,
+    }
+}
+
+fn is_allowed_host(host: &str, extra_hosts: &[String]) -> bool {
     let host = host.to_ascii_lowercase();
-    ALLOWED_HOSTS.iter().any(|h| *h == host)
+    ALLOWED_HOSTS.iter().any(|h| host_matches(h, &host))
+        || extra_hosts.iter().any(|h| host_matches(h, &host))
 }
 
-/// Open a URL in the user's default browser.
+/// Replace the user-configured host allowlist and persist it.
 ///
-/// Security:
-/// - Only allows https:// URLs.
-/// - Enforces a host allowlist to avoid exfil/phishing primitives.
-/// - Uses platform openers without invoking a shell.
+/// The compile-time defaults are always allowed and need not be included.
 #[tauri::command]
-pub fn open_external_url(url: String) -> Result<(), String> {
-    let parsed = Url::parse(&url).map_err(|_| "Invalid URL".to_string())?;
+pub fn set_allowed_hosts(state: State<'_, AllowedHosts>, hosts: Vec<String>) -> Result<(), String> {
+    let mut normalized: Vec<String> = hosts
+        .iter()
+        .map(|h| normalize_host(h))
+        .collect::<Result<_, _>>()?;
+    normalized.sort();
+    normalized.dedup();
+
+    let json = serde_json::to_string_pretty(&normalized)
+        .map_err(|e| format!("Failed to serialize allowed hosts: {}", e))?;
+    config::write_private(&get_allowed_hosts_path(), json.as_bytes())
+        .map_err(|e| format!("Failed to save allowed hosts: {}", e))?;
+
+    log::info!(
+        "Updated external URL allowlist ({} hosts)",
+        normalized.len()
+    );
+    *state.hosts.lock() = normalized;
+    Ok(())
+}
+
+/// Get the user-configured host allowlist (excluding compile-time defaults).
+#[tauri::command]
+pub fn get_allowed_hosts(state: State<'_, AllowedHosts>) -> Result<Vec<String>, String> {
+    Ok(state.hosts.lock().clone())
+}
+
+/// Parse `url` and apply the scheme and host checks for opening it.
+fn validate_external_url(url: &str, extra_hosts: &[String]) -> Result<Url, String> {
+    let parsed = Url::parse(url).map_err(|_| "Invalid URL".to_string())?;
 
     if parsed.scheme() != "https" {
         return Err("Only https:// URLs are allowed".to_string());
@@ -30,25 +153,215 @@ pub fn open_external_url(url: String) -> Result<(), String> {
         .host_str()
         .ok_or_else(|| "URL host is required".to_string())?;
 
-    if !is_allowed_host(host) {
+    if !is_allowed_host(host, extra_hosts) {
         return Err("Blocked external URL host".to_string());
     }
 
-    // Never spawn a shell; call the platform opener directly.
+    Ok(parsed)
+}
+
+/// Result of `check_external_url`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UrlCheck {
+    pub allowed: bool,
+    /// Why the URL would be refused, when it isn't allowed.
+    pub reason: Option<String>,
+}
+
+/// Report whether `open_external_url` would open `url`, without opening it.
+#[tauri::command]
+pub fn check_external_url(state: State<'_, AllowedHosts>, url: String) -> UrlCheck {
+    match validate_external_url(&url, &state.hosts.lock()) {
+        Ok(_) => UrlCheck {
+            allowed: true,
+            reason: None,
+        },
+        Err(reason) => UrlCheck {
+            allowed: false,
+            reason: Some(reason),
+        },
+    }
+}
+
+/// Open a URL in the user's default browser.
+///
+/// Security:
+/// - Only allows https:// URLs.
+/// - Enforces a host allowlist (defaults plus `set_allowed_hosts`) to avoid
+///   exfil/phishing primitives.
+/// - Uses platform openers without invoking a shell (`open` on macOS,
+///   `xdg-open` on Linux, `rundll32` on Windows).
+#[tauri::command]
+pub fn open_external_url(state: State<'_, AllowedHosts>, url: String) -> Result<(), String> {
+    let parsed = validate_external_url(&url, &state.hosts.lock())?;
+
+    // Never spawn a shell; call the platform opener directly with the URL
+    // as a single argument.
     #[cfg(target_os = "macos")]
     {
-        std::process::Command::new("/usr/bin/open")
-            .arg(parsed.as_str())
-            .status()
-            .map_err(|e| format!("Failed to open URL: {e}"))?
-            .success()
-            .then_some(())
-            .ok_or_else(|| "Failed to open URL".to_string())
+        run_opener("/usr/bin/open", &[parsed.as_str()], "URL")
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        run_opener(
+            "rundll32",
+            &["url.dll,FileProtocolHandler", parsed.as_str()],
+            "URL",
+        )
     }
 
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        run_opener("xdg-open", &[parsed.as_str()], "URL")
+    }
+
+    #[cfg(not(any(unix, target_os = "windows")))]
     {
         let _ = parsed;
-        Err("open_external_url is only supported on macOS".to_string())
+        Err("open_external_url is not supported on this platform".to_string())
+    }
+}
+
+#[cfg(any(unix, target_os = "windows"))]
+fn run_opener(program: &str, args: &[&str], what: &str) -> Result<(), String> {
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                format!("{what} opener '{program}' was not found on this system")
+            }
+            _ => format!("Failed to open {what}: {e}"),
+        })?;
+
+    status
+        .success()
+        .then_some(())
+        .ok_or_else(|| format!("Failed to open {what}"))
+}
+
+/// Directories `reveal_in_file_manager` and `read_file_for_context` may
+/// access paths under.
+pub(crate) fn user_roots() -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = dirs::home_dir().into_iter().collect();
+    // Mounted volumes (external drives, disk images).
+    if cfg!(target_os = "macos") {
+        roots.push(PathBuf::from("/Volumes"));
+    }
+    roots
+}
+
+/// Canonicalize `path` and check that it exists under one of `roots`.
+/// Guards every command that opens or reads a path the user gave.
+pub(crate) fn resolve_user_path(path: &Path, roots: &[PathBuf]) -> Result<PathBuf, String> {
+    if path.to_string_lossy().contains('\0') {
+        return Err("Path must not contain NUL bytes".to_string());
+    }
+    if !path.is_absolute() {
+        return Err("Path must be absolute".to_string());
+    }
+    // Resolves `..` and symlinks, so the root check can't be escaped.
+    let resolved =
+        fs::canonicalize(path).map_err(|e| format!("Cannot access {}: {}", path.display(), e))?;
+    let allowed = roots
+        .iter()
+        .any(|root| fs::canonicalize(root).is_ok_and(|root| resolved.starts_with(root)));
+    if allowed {
+        Ok(resolved)
+    } else {
+        Err(format!(
+            "Path is outside the allowed directories: {}",
+            path.display()
+        ))
+    }
+}
+
+/// Show a file or directory in the system file manager.
+///
+/// The path must be absolute, exist, and resolve (after following
+/// symlinks) to somewhere inside the user's home directory or, on macOS,
+/// `/Volumes`, so this can't be used to open arbitrary files. Uses
+/// `open -R` on macOS, Explorer `/select,` on Windows, and `xdg-open` on
+/// the containing directory elsewhere, without invoking a shell.
+#[tauri::command]
+pub fn reveal_in_file_manager(path: String) -> Result<(), String> {
+    let resolved = resolve_user_path(Path::new(&path), &user_roots())?;
+
+    #[cfg(target_os = "macos")]
+    {
+        let target = resolved.to_string_lossy();
+        run_opener("/usr/bin/open", &["-R", target.as_ref()], "file manager")
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        // Explorer exits non-zero even on success, so only spawning is checked.
+        std::process::Command::new("explorer")
+            .arg(format!("/select,{}", resolved.display()))
+            .spawn()
+            .map(|_| ())
+            .map_err(|e| format!("Failed to open file manager: {e}"))
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let dir = if resolved.is_dir() {
+            resolved.as_path()
+        } else {
+            resolved.parent().unwrap_or(&resolved)
+        };
+        run_opener(
+            "xdg-open",
+            &[dir.to_string_lossy().as_ref()],
+            "file manager",
+        )
+    }
+
+    #[cfg(not(any(unix, target_os = "windows")))]
+    {
+        let _ = resolved;
+        Err("reveal_in_file_manager is not supported on this platform".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reveals_only_paths_inside_roots() {
+        let root = std::env::temp_dir().join(format!("reveal-{}", uuid::Uuid::new_v4()));
+        let inside = root.join("inside");
+        fs::create_dir_all(&inside).unwrap();
+        let roots = vec![inside.clone()];
+
+        assert!(resolve_user_path(&inside, &roots).is_ok());
+        let escape = inside.join("..");
+        assert!(resolve_user_path(&escape, &roots).is_err());
+        assert!(resolve_user_path(&root, &roots).is_err());
+        assert!(resolve_user_path(Path::new("inside"), &roots).is_err());
+        assert!(resolve_user_path(Path::new("/tmp/\0"), &roots).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn normalizes_wildcard_hosts() {
+        assert_eq!(normalize_host("*.Example.com").unwrap(), "*.example.com");
+        assert!(normalize_host("*.com").is_err());
+        assert!(normalize_host("*").is_err());
+        assert!(normalize_host("*.*.example.com").is_err());
+        assert!(normalize_host("foo.*.example.com").is_err());
+    }
+
+    #[test]
+    fn wildcard_matches_subdomains_only() {
+        let extra = vec!["*.example.com".to_string()];
+        assert!(is_allowed_host("docs.example.com", &extra));
+        assert!(is_allowed_host("a.b.example.com", &extra));
+        assert!(!is_allowed_host("example.com", &extra));
+        assert!(!is_allowed_host("evil-example.com", &extra));
+        assert!(!is_allowed_host("example.com.evil.net", &extra));
     }
 }
@@ -0,0 +1,194 @@
+use serde::Serialize;
+use std::process::Command;
+use std::time::Duration;
+use tauri::AppHandle;
+
+use crate::error::CommandError;
+use crate::process::output_with_timeout;
+
+/// Upper bound on how long `git status` may run before we give up.
+const GIT_STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Summary of the git state of a directory, for AI context.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct GitStatus {
+    pub is_repo: bool,
+    pub cwd: String,
+    pub branch: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: u32,
+    pub behind: u32,
+    pub staged: Vec<String>,
+    pub unstaged: Vec<String>,
+    pub untracked: Vec<String>,
+    pub conflicted: Vec<String>,
+}
+
+/// Get the git status of a session's current directory.
+///
+/// Runs `git status --porcelain=v2 --branch` directly (no shell), with the
+/// repository's fsmonitor and hooks disabled. Returns `is_repo: false` when
+/// git exits non-zero, e.g. outside a repository.
+#[tauri::command(async)]
+pub fn get_git_status(app: AppHandle, session_id: String) -> Result<GitStatus, CommandError> {
+    let cwd = crate::pty::get_cwd(app, session_id, None)?;
+
+    let output = output_with_timeout(
+        Command::new("git")
+            // The repo's config is untrusted: don't let it run an fsmonitor
+            // hook or other commands.
+            .args([
+                "-c",
+                "core.fsmonitor=false",
+                "-c",
+                "core.untrackedCache=false",
+                "-c",
+                "core.hooksPath=/dev/null",
+            ])
+            .args(["status", "--porcelain=v2", "--branch", "-z"])
+            .current_dir(&cwd)
+            // Don't take index.lock; a concurrent user `git` command wins.
+            .env("GIT_OPTIONAL_LOCKS", "0"),
+        GIT_STATUS_TIMEOUT,
+    )
+    .map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            CommandError::NotSupported("git is not installed".to_string())
+        }
+        _ => CommandError::io("Failed to run git", e),
+    })?
+    .ok_or_else(|| CommandError::Io("git status timed out".to_string()))?;
+
+    if !output.status.success() {
+        return Ok(GitStatus {
+            cwd,
+            ..GitStatus::default()
+        });
+    }
+
+    let mut status = parse_porcelain_v2(&String::from_utf8_lossy(&output.stdout));
+    status.cwd = cwd;
+    Ok(status)
+}
+
+/// Parse NUL-separated `git status --porcelain=v2 --branch -z` output.
+fn parse_porcelain_v2(output: &str) -> GitStatus {
+    let mut status = GitStatus {
+        is_repo: true,
+        ..GitStatus::default()
+    };
+
+    let mut records = output.split('\0').filter(|r| !r.is_empty());
+    while let Some(record) = records.next() {
+        if let Some(header) = record.strip_prefix("# ") {
+            parse_branch_header(header, &mut status);
+            continue;
+        }
+
+        if !record.is_char_boundary(1) {
+            continue;
+        }
+        let (kind, rest) = record.split_at(1);
+        match kind {
+            // 1 XY sub mH mI mW hH hI path
+            "1" => {
+                if let Some((xy, path)) = split_entry(rest, 6) {
+                    push_changed(&mut status, xy, path);
+                }
+            }
+            // 2 XY sub mH mI mW hH hI Xscore path, followed by origPath
+            "2" => {
+                if let Some((xy, path)) = split_entry(rest, 7) {
+                    push_changed(&mut status, xy, path);
+                }
+                let _ = records.next();
+            }
+            // u XY sub m1 m2 m3 mW h1 h2 h3 path
+            "u" => {
+                if let Some((_, path)) = split_entry(rest, 8) {
+                    status.conflicted.push(path.to_string());
+                }
+            }
+            "?" => status.untracked.push(rest.trim_start().to_string()),
+            _ => {}
+        }
+    }
+
+    status
+}
+
+fn parse_branch_header(header: &str, status: &mut GitStatus) {
+    if let Some(head) = header.strip_prefix("branch.head ") {
+        if head != "(detached)" {
+            status.branch = Some(head.to_string());
+        }
+    } else if let Some(upstream) = header.strip_prefix("branch.upstream ") {
+        status.upstream = Some(upstream.to_string());
+    } else if let Some(ab) = header.strip_prefix("branch.ab ") {
+        for part in ab.split_whitespace() {
+            if let Some(n) = part.strip_prefix('+') {
+                status.ahead = n.parse().unwrap_or(0);
+            } else if let Some(n) = part.strip_prefix('-') {
+                status.behind = n.parse().unwrap_or(0);
+            }
+        }
+    }
+}
+
+/// Split `" XY f1 .. fN path"` into the XY code and the path, where the
+/// path (which may contain spaces) follows `fields` metadata fields.
+fn split_entry(rest: &str, fields: usize) -> Option<(&str, &str)> {
+    let mut parts = rest.trim_start().splitn(fields + 2, ' ');
+    let xy = parts.next()?;
+    let path = parts.nth(fields)?;
+    Some((xy, path))
+}
+
+fn push_changed(status: &mut GitStatus, xy: &str, path: &str) {
+    let mut codes = xy.chars();
+    if codes.next().is_some_and(|c| c != '.') {
+        status.staged.push(path.to_string());
+    }
+    if codes.next().is_some_and(|c| c != '.') {
+        status.unstaged.push(path.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_branch_and_entries() {
+        let output = [
+            "# branch.oid 1234567890abcdef",
+            "# branch.head main",
+            "# branch.upstream origin/main",
+            "# branch.ab +2 -1",
+            "1 M. N... 100644 100644 100644 aaaa bbbb src/staged.rs",
+            "1 .M N... 100644 100644 100644 aaaa bbbb src/with space.rs",
+            "2 R. N... 100644 100644 100644 aaaa bbbb R100 new.rs",
+            "old.rs",
+            "u UU N... 100644 100644 100644 100644 aaaa bbbb cccc conflict.rs",
+            "? notes.txt",
+            "",
+        ]
+        .join("\0");
+
+        let status = parse_porcelain_v2(&output);
+        assert!(status.is_repo);
+        assert_eq!(status.branch.as_deref(), Some("main"));
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!((status.ahead, status.behind), (2, 1));
+        assert_eq!(status.staged, vec!["src/staged.rs", "new.rs"]);
+        assert_eq!(status.unstaged, vec!["src/with space.rs"]);
+        assert_eq!(status.conflicted, vec!["conflict.rs"]);
+        assert_eq!(status.untracked, vec!["notes.txt"]);
+    }
+
+    #[test]
+    fn detached_head_has_no_branch() {
+        let status = parse_porcelain_v2("# branch.oid abc\0# branch.head (detached)\0");
+        assert_eq!(status.branch, None);
+    }
+}
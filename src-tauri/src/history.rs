@@ -0,0 +1,235 @@
+use std::path::PathBuf;
+
+use crate::error::CommandError;
+use crate::logger::redact_secrets;
+
+/// Default and maximum number of commands returned by `get_shell_history`.
+const DEFAULT_HISTORY_LIMIT: usize = 100;
+const MAX_HISTORY_LIMIT: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HistoryFormat {
+    Zsh,
+    Bash,
+    Fish,
+}
+
+/// Locate the history file for the user's `$SHELL`, honoring `$HISTFILE`.
+fn history_source() -> Option<(HistoryFormat, PathBuf)> {
+    let shell = std::env::var("SHELL").unwrap_or_default();
+    let home = dirs::home_dir()?;
+    let histfile = std::env::var_os("HISTFILE").map(PathBuf::from);
+
+    match shell.rsplit('/').next().unwrap_or("") {
+        "zsh" => Some((
+            HistoryFormat::Zsh,
+            histfile.unwrap_or_else(|| home.join(".zsh_history")),
+        )),
+        "bash" => Some((
+            HistoryFormat::Bash,
+            histfile.unwrap_or_else(|| home.join(".bash_history")),
+        )),
+        // fish follows XDG on every platform, macOS included.
+        "fish" => {
+            let data = std::env::var_os("XDG_DATA_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| home.join(".local/share"));
+            Some((HistoryFormat::Fish, data.join("fish").join("fish_history")))
+        }
+        _ => None,
+    }
+}
+
+/// Get the user's most recent shell commands, newest first, with secrets
+/// redacted.
+///
+/// Reads `~/.zsh_history` (including the `: <ts>:<dur>;cmd` extended
+/// format), `~/.bash_history` or fish's history file based on `$SHELL`.
+/// Returns an empty list if the shell is unrecognized or the file doesn't
+/// exist. `limit` defaults to 100.
+#[tauri::command]
+pub fn get_shell_history(limit: Option<usize>) -> Result<Vec<String>, CommandError> {
+    let limit = limit
+        .unwrap_or(DEFAULT_HISTORY_LIMIT)
+        .min(MAX_HISTORY_LIMIT);
+    let Some((format, path)) = history_source() else {
+        return Ok(Vec::new());
+    };
+
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(CommandError::io("Failed to read shell history", e)),
+    };
+
+    let commands = match format {
+        HistoryFormat::Zsh => parse_zsh_history(&String::from_utf8_lossy(&unmetafy(&bytes))),
+        HistoryFormat::Bash => parse_bash_history(&String::from_utf8_lossy(&bytes)),
+        HistoryFormat::Fish => parse_fish_history(&String::from_utf8_lossy(&bytes)),
+    };
+
+    Ok(commands
+        .iter()
+        .rev()
+        .take(limit)
+        .map(|c| redact_secrets(c))
+        .collect())
+}
+
+/// Undo zsh's metafication: byte 0x83 marks the next byte as XOR 0x20.
+fn unmetafy(bytes: &[u8]) -> Vec<u8> {
+    const META: u8 = 0x83;
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(&b) = iter.next() {
+        if b == META {
+            if let Some(&next) = iter.next() {
+                out.push(next ^ 0x20);
+            }
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+/// Parse zsh history, plain or extended. Multi-line commands are stored
+/// with a trailing backslash on every line but the last.
+fn parse_zsh_history(contents: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current: Option<String> = None;
+
+    for line in contents.lines() {
+        let (text, continues) = match line.strip_suffix('\\') {
+            Some(text) => (text, true),
+            None => (line, false),
+        };
+
+        match current.as_mut() {
+            Some(command) => {
+                command.push('\n');
+                command.push_str(text);
+            }
+            None => current = Some(strip_zsh_extended_prefix(text).to_string()),
+        }
+
+        if !continues {
+            if let Some(command) = current.take() {
+                if !command.trim().is_empty() {
+                    commands.push(command);
+                }
+            }
+        }
+    }
+    if let Some(command) = current {
+        if !command.trim().is_empty() {
+            commands.push(command);
+        }
+    }
+    commands
+}
+
+/// Strip the `: <timestamp>:<duration>;` prefix of an extended history line.
+fn strip_zsh_extended_prefix(line: &str) -> &str {
+    let Some(rest) = line.strip_prefix(": ") else {
+        return line;
+    };
+    match rest.split_once(';') {
+        Some((meta, command))
+            if meta
+                .split(':')
+                .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())) =>
+        {
+            command
+        }
+        _ => line,
+    }
+}
+
+/// Parse bash history, skipping `#<timestamp>` lines written when
+/// `HISTTIMEFORMAT` is set.
+fn parse_bash_history(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter(|line| {
+            !line
+                .strip_prefix('#')
+                .is_some_and(|ts| !ts.is_empty() && ts.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse fish's YAML-like history (`- cmd: ...` entries).
+fn parse_fish_history(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter_map(|line| line.strip_prefix("- cmd: "))
+        .map(unescape_fish)
+        .filter(|command| !command.trim().is_empty())
+        .collect()
+}
+
+/// Fish escapes newlines as `\n` and backslashes as `\\`.
+fn unescape_fish(escaped: &str) -> String {
+    let mut out = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_zsh_extended_and_multiline() {
+        let contents = ": 1700000000:0;ls -la\n\
+                        : 1700000005:2;for f in *; do\\\n  echo $f\\\ndone\n\
+                        git status\n";
+        assert_eq!(
+            parse_zsh_history(contents),
+            vec!["ls -la", "for f in *; do\n  echo $f\ndone", "git status"]
+        );
+    }
+
+    #[test]
+    fn unmetafies_zsh_bytes() {
+        // "é" is 0xC3 0xA9; zsh stores 0xA9 as 0x83 0x89.
+        assert_eq!(unmetafy(&[b'x', 0xC3, 0x83, 0x89]), "xé".as_bytes());
+    }
+
+    #[test]
+    fn parses_bash_with_timestamps() {
+        let contents = "#1700000000\nmake\n#1700000001\ncargo test\n# not a timestamp\n";
+        assert_eq!(
+            parse_bash_history(contents),
+            vec!["make", "cargo test", "# not a timestamp"]
+        );
+    }
+
+    #[test]
+    fn parses_fish_entries() {
+        let contents = "- cmd: echo hi\n  when: 1700000000\n- cmd: printf 'a\\\\nb'\\necho done\n";
+        assert_eq!(
+            parse_fish_history(contents),
+            vec!["echo hi", "printf 'a\\nb'\necho done"]
+        );
+    }
+}
@@ -1,52 +1,600 @@
-use security_framework::passwords::{
-    delete_generic_password, get_generic_password, set_generic_password,
-};
+use keyring::Entry;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::log_crypto;
 
 const SERVICE_NAME: &str = "com.aiterminal.app";
 
-/// Store an API key in macOS Keychain.
+/// Keychain account holding a JSON array of accounts with stored keys.
+/// Keychain backends can't reliably enumerate items by service, so we
+/// maintain this index alongside the keys themselves.
+const PROVIDER_INDEX_ACCOUNT: &str = "__providers__";
+
+/// Keychain account read by `keychain_available`. Never written.
+const PROBE_ACCOUNT: &str = "__access_probe__";
+
+/// Set by `panic_lock`; `get_api_key` refuses to return keys until
+/// `unlock_api_keys`. Held in memory only, so a restart also unlocks.
+static KEYS_LOCKED: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn lock_api_keys() {
+    KEYS_LOCKED.store(true, Ordering::Release);
+}
+
+/// Let `get_api_key` return keys again after `panic_lock`.
 #[tauri::command]
-pub fn store_api_key(provider: String, api_key: String) -> Result<(), String> {
+pub fn unlock_api_keys() {
+    if KEYS_LOCKED.swap(false, Ordering::AcqRel) {
+        log::info!("Unlocked API keys");
+    }
+}
+
+/// Build a credential entry for a provider under the app's service namespace.
+///
+/// On macOS this maps to the same generic password item (service + account)
+/// that earlier releases wrote via Security.framework, so existing keys stay
+/// readable. Linux uses Secret Service and Windows uses Credential Manager.
+fn entry_for(provider: &str) -> Result<Entry, String> {
+    entry_in(SERVICE_NAME, provider)
+}
+
+fn entry_in(service: &str, account: &str) -> Result<Entry, String> {
+    Entry::new(service, account)
+        .map_err(|e| format!("Failed to open keychain entry for {}: {}", account, e))
+}
+
+fn read_provider_index() -> Result<Vec<String>, String> {
+    read_provider_index_in(SERVICE_NAME)
+}
+
+fn read_provider_index_in(service: &str) -> Result<Vec<String>, String> {
+    match entry_in(service, PROVIDER_INDEX_ACCOUNT)?.get_password() {
+        Ok(json) => serde_json::from_str(&json)
+            .map_err(|e| format!("Failed to parse provider index: {}", e)),
+        Err(keyring::Error::NoEntry) => Ok(Vec::new()),
+        Err(e) => Err(format!("Failed to read provider index: {}", e)),
+    }
+}
+
+fn write_provider_index(providers: &[String]) -> Result<(), String> {
+    let entry = entry_for(PROVIDER_INDEX_ACCOUNT)?;
+    if providers.is_empty() {
+        return match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to clear provider index: {}", e)),
+        };
+    }
+
+    let json = serde_json::to_string(providers)
+        .map_err(|e| format!("Failed to serialize provider index: {}", e))?;
+    let _ = entry.delete_credential();
+    entry
+        .set_password(&json)
+        .map_err(|e| format!("Failed to write provider index: {}", e))
+}
+
+/// Separates provider and label in a labelled key's account name.
+const LABEL_SEPARATOR: char = ':';
+
+fn validate_provider(provider: &str) -> Result<(), String> {
+    if provider.is_empty()
+        || provider == PROVIDER_INDEX_ACCOUNT
+        || provider == PROBE_ACCOUNT
+        || provider.contains(LABEL_SEPARATOR)
+    {
+        return Err(format!("Invalid provider name: {:?}", provider));
+    }
+    Ok(())
+}
+
+/// Keychain account for a provider's key: `provider` for the default key,
+/// `provider:label` for a named one.
+fn account_name(provider: &str, label: Option<&str>) -> Result<String, String> {
+    validate_provider(provider)?;
+    match label {
+        None => Ok(provider.to_string()),
+        Some(label) if label.is_empty() || label.contains(LABEL_SEPARATOR) => {
+            Err(format!("Invalid key label: {:?}", label))
+        }
+        Some(label) => Ok(format!("{}{}{}", provider, LABEL_SEPARATOR, label)),
+    }
+}
+
+/// Split an account name back into provider and optional label.
+fn split_account(account: &str) -> (&str, Option<&str>) {
+    match account.split_once(LABEL_SEPARATOR) {
+        Some((provider, label)) => (provider, Some(label)),
+        None => (account, None),
+    }
+}
+
+/// Shortest plausible key for providers with a known format. Real keys
+/// are much longer; this only catches truncated pastes.
+const MIN_KEY_LENGTH: usize = 40;
+
+/// Reject keys that clearly don't match the provider's format. Errors
+/// describe the problem without echoing the key.
+fn validate_key_format(provider: &str, api_key: &str) -> Result<(), String> {
+    if api_key.trim().is_empty() {
+        return Err(format!("API key for {} is empty", provider));
+    }
+    let prefix = match provider {
+        "openai" if api_key.starts_with("sk-ant-") => {
+            return Err("This looks like an Anthropic key, not an OpenAI key".to_string());
+        }
+        "openai" => "sk-",
+        "anthropic" => "sk-ant-",
+        // Unknown providers and local gateways have no fixed format.
+        _ => return Ok(()),
+    };
+    if api_key.chars().any(char::is_whitespace) {
+        return Err(format!("API key for {} contains whitespace", provider));
+    }
+    if !api_key.starts_with(prefix) {
+        return Err(format!(
+            "API key for {} should start with {}",
+            provider, prefix
+        ));
+    }
+    if api_key.len() < MIN_KEY_LENGTH {
+        return Err(format!(
+            "API key for {} is too short ({} characters); it may be truncated",
+            provider,
+            api_key.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Store an API key in the platform keychain.
+///
+/// `label` names one of several keys for the same provider (e.g. `work`);
+/// omit it for the provider's default key.
+///
+/// Keys for `openai` and `anthropic` are checked against the provider's
+/// key format first; pass `skip_validation` for gateways that issue keys
+/// in another format.
+#[tauri::command]
+pub fn store_api_key(
+    provider: String,
+    api_key: String,
+    label: Option<String>,
+    skip_validation: Option<bool>,
+) -> Result<(), String> {
+    let account = account_name(&provider, label.as_deref())?;
+    if !skip_validation.unwrap_or(false) {
+        validate_key_format(&provider, &api_key)?;
+    }
+    let entry = entry_for(&account)?;
+    // Read first: if the index can't be read, rewriting it would drop
+    // every other provider.
+    let mut accounts = read_provider_index()?;
+
     // Delete existing entry first (if any) to avoid conflicts
-    let _ = delete_generic_password(SERVICE_NAME, &provider);
+    let _ = entry.delete_credential();
+
+    entry
+        .set_password(&api_key)
+        .map_err(|e| format!("Failed to store API key for {}: {}", account, e))?;
+
+    if !accounts.contains(&account) {
+        accounts.push(account.clone());
+        accounts.sort();
+        write_provider_index(&accounts)?;
+    }
+
+    log::info!("Stored API key for provider: {}", account);
+    Ok(())
+}
 
-    set_generic_password(SERVICE_NAME, &provider, api_key.as_bytes())
-        .map_err(|e| format!("Failed to store API key for {}: {}", provider, e))?;
+/// Retrieve an API key from the platform keychain. Fails while keys are
+/// locked by `panic_lock`.
+#[tauri::command]
+pub fn get_api_key(provider: String, label: Option<String>) -> Result<Option<String>, String> {
+    if KEYS_LOCKED.load(Ordering::Acquire) {
+        return Err("API keys are locked; unlock them to continue".to_string());
+    }
+    let account = account_name(&provider, label.as_deref())?;
+    let entry = entry_for(&account)?;
+    match entry.get_password() {
+        Ok(key) => Ok(Some(key)),
+        // No key stored — not an error
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(keyring::Error::BadEncoding(_)) => {
+            Err(format!("Invalid UTF-8 in stored key for {}", account))
+        }
+        Err(e) => Err(format!("Failed to retrieve API key for {}: {}", account, e)),
+    }
+}
+
+/// Delete an API key from the platform keychain.
+#[tauri::command]
+pub fn delete_api_key(provider: String, label: Option<String>) -> Result<(), String> {
+    let account = account_name(&provider, label.as_deref())?;
+    let mut accounts = read_provider_index()?;
+    entry_for(&account)?
+        .delete_credential()
+        .map_err(|e| format!("Failed to delete API key for {}: {}", account, e))?;
+
+    if let Some(pos) = accounts.iter().position(|a| *a == account) {
+        accounts.remove(pos);
+        write_provider_index(&accounts)?;
+    }
 
-    log::info!("Stored API key for provider: {}", provider);
+    log::info!("Deleted API key for provider: {}", account);
     Ok(())
 }
 
-/// Retrieve an API key from macOS Keychain.
-#[tauri::command]
-pub fn get_api_key(provider: String) -> Result<Option<String>, String> {
-    match get_generic_password(SERVICE_NAME, &provider) {
-        Ok(bytes) => {
-            let key = String::from_utf8(bytes.to_vec())
-                .map_err(|e| format!("Invalid UTF-8 in stored key: {}", e))?;
-            Ok(Some(key))
-        }
-        Err(e) => {
-            let err_str = e.to_string();
-            // errSecItemNotFound (-25300) means no key stored — not an error
-            if err_str.contains("-25300") || err_str.contains("not found") {
-                Ok(None)
-            } else {
-                Err(format!(
-                    "Failed to retrieve API key for {}: {}",
-                    provider, e
+/// List providers that currently have at least one stored API key.
+#[tauri::command]
+pub fn list_api_key_providers() -> Result<Vec<String>, String> {
+    let mut providers: Vec<String> = read_provider_index()?
+        .iter()
+        .map(|account| split_account(account).0.to_string())
+        .collect();
+    providers.sort();
+    providers.dedup();
+    Ok(providers)
+}
+
+/// List the labels of a provider's stored keys; `None` is the default key.
+#[tauri::command]
+pub fn list_api_keys(provider: String) -> Result<Vec<Option<String>>, String> {
+    validate_provider(&provider)?;
+    Ok(read_provider_index()?
+        .iter()
+        .map(|account| split_account(account))
+        .filter(|(p, _)| *p == provider)
+        .map(|(_, label)| label.map(str::to_string))
+        .collect())
+}
+
+/// One stored key in an `export_keys` bundle.
+#[derive(Serialize, Deserialize)]
+struct ExportedKey {
+    /// Keychain account: `provider` or `provider:label`.
+    account: String,
+    key: String,
+}
+
+/// Plaintext of an `export_keys` bundle; only ever held in memory.
+#[derive(Serialize, Deserialize)]
+struct KeyBundle {
+    version: u32,
+    keys: Vec<ExportedKey>,
+}
+
+const KEY_BUNDLE_VERSION: u32 = 1;
+
+/// Export every stored API key as one blob encrypted with `passphrase`
+/// (Argon2id and XChaCha20-Poly1305), for moving keys to another machine
+/// with `import_keys`. Keys are never written to disk in plaintext; saving
+/// the returned blob is up to the caller. Fails while keys are locked.
+#[tauri::command]
+pub fn export_keys(passphrase: String) -> Result<Vec<u8>, String> {
+    if KEYS_LOCKED.load(Ordering::Acquire) {
+        return Err("API keys are locked; unlock them to continue".to_string());
+    }
+    let mut bundle = KeyBundle {
+        version: KEY_BUNDLE_VERSION,
+        keys: Vec::new(),
+    };
+    for account in read_provider_index()? {
+        match entry_for(&account)?.get_password() {
+            Ok(key) => bundle.keys.push(ExportedKey { account, key }),
+            // Stale index entry; nothing to export.
+            Err(keyring::Error::NoEntry) => {}
+            Err(e) => return Err(format!("Failed to read API key for {}: {}", account, e)),
+        }
+    }
+
+    let json =
+        serde_json::to_vec(&bundle).map_err(|e| format!("Failed to serialize API keys: {}", e))?;
+    let sealed = log_crypto::seal_with_passphrase(&passphrase, &json)?;
+    log::info!("Exported {} API keys", bundle.keys.len());
+    Ok(sealed)
+}
+
+/// Decrypt a bundle from `export_keys` and store each key in it, replacing
+/// any existing key for the same provider and label. Returns the number of
+/// keys imported.
+///
+/// Keys aren't format-checked, since the exporting machine already
+/// accepted them. Nothing is stored unless the whole bundle decrypts.
+#[tauri::command]
+pub fn import_keys(bundle: Vec<u8>, passphrase: String) -> Result<usize, String> {
+    let json = log_crypto::open_with_passphrase(&passphrase, &bundle)?;
+    let bundle: KeyBundle =
+        serde_json::from_slice(&json).map_err(|e| format!("Invalid key bundle: {}", e))?;
+    if bundle.version != KEY_BUNDLE_VERSION {
+        return Err(format!(
+            "Unsupported key bundle version: {}",
+            bundle.version
+        ));
+    }
+
+    for ExportedKey { account, key } in &bundle.keys {
+        let (provider, label) = split_account(account);
+        store_api_key(
+            provider.to_string(),
+            key.clone(),
+            label.map(str::to_string),
+            Some(true),
+        )?;
+    }
+    log::info!("Imported {} API keys", bundle.keys.len());
+    Ok(bundle.keys.len())
+}
+
+/// Copy the secret in `from` to `to`, verify it, then delete `from`.
+///
+/// Returns `Ok(false)` if `from` doesn't exist. Refuses to overwrite an
+/// existing `to`. Any failure before the final delete leaves `from` intact;
+/// a partially written `to` is removed again.
+fn move_entry(from: &Entry, to: &Entry, label: &str) -> Result<bool, String> {
+    let secret = match from.get_password() {
+        Ok(secret) => secret,
+        Err(keyring::Error::NoEntry) => return Ok(false),
+        Err(e) => return Err(format!("Failed to read {}: {}", label, e)),
+    };
+
+    match to.get_password() {
+        Err(keyring::Error::NoEntry) => {}
+        Ok(_) => return Err(format!("Destination for {} already exists", label)),
+        Err(e) => return Err(format!("Failed to check destination for {}: {}", label, e)),
+    }
+
+    let written = to
+        .set_password(&secret)
+        .and_then(|()| to.get_password())
+        .map_err(|e| format!("Failed to write {}: {}", label, e));
+    if written.as_deref() != Ok(secret.as_str()) {
+        let _ = to.delete_credential();
+        return Err(written
+            .err()
+            .unwrap_or_else(|| format!("Failed to verify copied {}", label)));
+    }
+
+    from.delete_credential()
+        .map_err(|e| format!("Copied {} but failed to delete the original: {}", label, e))?;
+    Ok(true)
+}
+
+/// Move a provider's stored API keys, labelled ones included, to a new
+/// provider name.
+///
+/// Returns `false` if `from_provider` had no keys. Fails without changes if
+/// `to_provider` already has a key under any of the same labels. Keys are
+/// moved one at a time; if one fails, the index still records those already
+/// moved and the rest stay under `from_provider`.
+#[tauri::command]
+pub fn migrate_api_key(from_provider: String, to_provider: String) -> Result<bool, String> {
+    validate_provider(&from_provider)?;
+    validate_provider(&to_provider)?;
+    if from_provider == to_provider {
+        return Ok(false);
+    }
+
+    let mut accounts = read_provider_index()?;
+    let mut moves = Vec::new();
+    for account in accounts.iter() {
+        let (provider, label) = split_account(account);
+        if provider == from_provider {
+            moves.push((account.clone(), account_name(&to_provider, label)?));
+        }
+    }
+    if moves.is_empty() {
+        return Ok(false);
+    }
+    for (_, target) in &moves {
+        match entry_for(target)?.get_password() {
+            Err(keyring::Error::NoEntry) => {}
+            Ok(_) => return Err(format!("Destination for API key {} already exists", target)),
+            Err(e) => {
+                return Err(format!(
+                    "Failed to check destination for API key {}: {}",
+                    target, e
                 ))
             }
         }
     }
+
+    let mut moved = 0;
+    let mut result = Ok(());
+    for (account, target) in &moves {
+        let label = format!("API key for {}", account);
+        match move_entry(&entry_for(account)?, &entry_for(target)?, &label) {
+            Ok(found) => {
+                accounts.retain(|a| a != account);
+                if found {
+                    accounts.push(target.clone());
+                    moved += 1;
+                }
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    accounts.sort();
+    accounts.dedup();
+    write_provider_index(&accounts)?;
+    result?;
+
+    log::info!(
+        "Migrated {} API keys from provider {} to {}",
+        moved,
+        from_provider,
+        to_provider
+    );
+    Ok(moved > 0)
 }
 
-/// Delete an API key from macOS Keychain.
+/// Move every indexed key from `old_service` to `new_service`, e.g. after
+/// the app's keychain namespace changes.
+///
+/// Keys are moved one at a time and the provider index last, so a failure
+/// leaves the remaining keys and the old index in place and the call can be
+/// retried. Returns `false` if `old_service` had nothing to move.
 #[tauri::command]
-pub fn delete_api_key(provider: String) -> Result<(), String> {
-    delete_generic_password(SERVICE_NAME, &provider)
-        .map_err(|e| format!("Failed to delete API key for {}: {}", provider, e))?;
+pub fn rename_service(old_service: String, new_service: String) -> Result<bool, String> {
+    if old_service.is_empty() || new_service.is_empty() {
+        return Err("Service names must not be empty".to_string());
+    }
+    if old_service == new_service {
+        return Ok(false);
+    }
 
-    log::info!("Deleted API key for provider: {}", provider);
-    Ok(())
+    let providers = read_provider_index_in(&old_service)?;
+    let mut moved = false;
+    for provider in &providers {
+        let label = format!("API key for {}", provider);
+        moved |= move_entry(
+            &entry_in(&old_service, provider)?,
+            &entry_in(&new_service, provider)?,
+            &label,
+        )?;
+    }
+
+    // Merge into any index already present under the new service.
+    let mut merged = read_provider_index_in(&new_service)?;
+    for provider in providers {
+        if !merged.contains(&provider) {
+            merged.push(provider);
+        }
+    }
+    merged.sort();
+    if !merged.is_empty() {
+        let json = serde_json::to_string(&merged)
+            .map_err(|e| format!("Failed to serialize provider index: {}", e))?;
+        let index = entry_in(&new_service, PROVIDER_INDEX_ACCOUNT)?;
+        let _ = index.delete_credential();
+        index
+            .set_password(&json)
+            .map_err(|e| format!("Failed to write provider index: {}", e))?;
+    }
+    match entry_in(&old_service, PROVIDER_INDEX_ACCOUNT)?.delete_credential() {
+        Ok(()) => moved = true,
+        Err(keyring::Error::NoEntry) => {}
+        Err(e) => return Err(format!("Failed to remove old provider index: {}", e)),
+    }
+
+    log::info!(
+        "Renamed keychain service {} to {}",
+        old_service,
+        new_service
+    );
+    Ok(moved)
+}
+
+/// Why the keychain can or can't be used, for onboarding guidance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeychainStatus {
+    Available,
+    /// The user declined the access prompt or the app isn't authorized.
+    Denied,
+    /// The keychain is locked and can't prompt right now.
+    Locked,
+    /// No usable keychain backend (e.g. no Secret Service on Linux).
+    Unavailable,
+}
+
+/// Result of `keychain_available`.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeychainAccess {
+    pub accessible: bool,
+    pub status: KeychainStatus,
+    pub reason: Option<String>,
+}
+
+/// Tell access failures apart from the platform's error message; keyring
+/// doesn't expose the underlying Security.framework / Secret Service codes.
+fn classify_access_error(message: &str) -> KeychainStatus {
+    let message = message.to_ascii_lowercase();
+    if message.contains("locked") || message.contains("interaction is not allowed") {
+        KeychainStatus::Locked
+    } else if message.contains("cancel")
+        || message.contains("denied")
+        || message.contains("not authorized")
+        || message.contains("not correct")
+    {
+        KeychainStatus::Denied
+    } else {
+        KeychainStatus::Unavailable
+    }
+}
+
+/// Probe whether the keychain is usable by reading a sentinel item.
+///
+/// A missing item means access works. Otherwise `status` says whether the
+/// user denied access, the keychain is locked, or there's no usable
+/// keychain at all, so onboarding can show the right guidance. May trigger
+/// the same permission prompt as `get_api_key`.
+#[tauri::command]
+pub fn keychain_available() -> KeychainAccess {
+    let result = entry_for(PROBE_ACCOUNT)
+        .map_err(|e| (KeychainStatus::Unavailable, e))
+        .and_then(|entry| match entry.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e @ keyring::Error::NoStorageAccess(_)) => {
+                let message = e.to_string();
+                let status = match classify_access_error(&message) {
+                    KeychainStatus::Unavailable => KeychainStatus::Locked,
+                    status => status,
+                };
+                Err((status, message))
+            }
+            Err(e) => {
+                let message = e.to_string();
+                Err((classify_access_error(&message), message))
+            }
+        });
+
+    match result {
+        Ok(()) => KeychainAccess {
+            accessible: true,
+            status: KeychainStatus::Available,
+            reason: None,
+        },
+        Err((status, reason)) => {
+            log::info!("Keychain not accessible ({:?}): {}", status, reason);
+            KeychainAccess {
+                accessible: false,
+                status,
+                reason: Some(reason),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_known_key_formats() {
+        let openai = format!("sk-proj-{}", "a".repeat(48));
+        let anthropic = format!("sk-ant-api03-{}", "b".repeat(90));
+        assert!(validate_key_format("openai", &openai).is_ok());
+        assert!(validate_key_format("anthropic", &anthropic).is_ok());
+
+        assert!(validate_key_format("openai", &anthropic).is_err());
+        assert!(validate_key_format("anthropic", &openai).is_err());
+        assert!(validate_key_format("openai", "sk-abc").is_err());
+        assert!(validate_key_format("openai", &format!("{} ", openai)).is_err());
+        assert!(validate_key_format("openai", "").is_err());
+
+        assert!(validate_key_format("local", "anything").is_ok());
+        assert!(validate_key_format("local", " ").is_err());
+    }
+
+    #[test]
+    fn validation_errors_never_contain_the_key() {
+        let key = "sk-ant-secret";
+        let error = validate_key_format("anthropic", key).unwrap_err();
+        assert!(!error.contains(key));
+    }
 }
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tauri::AppHandle;
+
+use crate::config;
+use crate::error::CommandError;
+use crate::pty;
+
+/// What's needed to re-create one tab: where it was and how big.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionDescriptor {
+    pub cwd: String,
+    pub title: Option<String>,
+    pub rows: u16,
+    pub cols: u16,
+}
+
+fn get_layout_path() -> PathBuf {
+    config::config_path("session_layout.json")
+}
+
+/// Save the cwd, title and size of every open session and return the count.
+///
+/// `order` lists session IDs in tab order; sessions not in it are appended.
+/// Cwds are looked up for all sessions at once, as in `get_cwd_all`.
+#[tauri::command(async)]
+pub fn save_session_layout(
+    app: AppHandle,
+    order: Option<Vec<String>>,
+) -> Result<usize, CommandError> {
+    let mut session_ids: Vec<String> = pty::list_sessions(app.clone())?
+        .into_iter()
+        .filter(|s| s.alive)
+        .map(|s| s.session_id)
+        .collect();
+    if let Some(order) = order {
+        session_ids.sort_by_key(|id| order.iter().position(|o| o == id).unwrap_or(usize::MAX));
+    }
+
+    let mut cwds = pty::get_cwd_all(app.clone());
+    let mut descriptors = Vec::with_capacity(session_ids.len());
+    for session_id in session_ids {
+        // Sessions can exit while we walk the list; skip them.
+        let (Some(cwd), Ok(title), Ok(size)) = (
+            cwds.remove(&session_id),
+            pty::get_session_title(app.clone(), session_id.clone()),
+            pty::get_pty_size(app.clone(), session_id.clone()),
+        ) else {
+            continue;
+        };
+        descriptors.push(SessionDescriptor {
+            cwd,
+            title,
+            rows: size.rows,
+            cols: size.cols,
+        });
+    }
+
+    let json = serde_json::to_string_pretty(&descriptors)
+        .map_err(|e| CommandError::Io(format!("Failed to serialize session layout: {}", e)))?;
+    config::write_private(&get_layout_path(), json.as_bytes())
+        .map_err(|e| CommandError::io("Failed to save session layout", e))?;
+
+    log::info!("Saved session layout ({} sessions)", descriptors.len());
+    Ok(descriptors.len())
+}
+
+/// Read the layout saved by `save_session_layout`.
+///
+/// Processes can't be resurrected across restarts, so this only returns
+/// descriptors; the frontend re-creates each tab with `spawn_shell` using
+/// the saved `cwd` and size. A saved directory may no longer exist.
+/// Returns an empty list when nothing has been saved.
+#[tauri::command]
+pub fn restore_session_layout() -> Result<Vec<SessionDescriptor>, CommandError> {
+    let json = match fs::read_to_string(get_layout_path()) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(CommandError::io("Failed to read session layout", e)),
+    };
+    serde_json::from_str(&json)
+        .map_err(|e| CommandError::Io(format!("Failed to parse session layout: {}", e)))
+}
@@ -1,36 +1,164 @@
+mod clipboard;
+mod config;
+mod error;
 mod external;
+mod git;
+mod history;
 mod keychain;
+mod layout;
+mod log_crypto;
 mod logger;
+mod osc;
+mod panic_mode;
+mod policy;
+mod process;
 mod pty;
+mod pty_backend;
+mod recording;
+mod system;
 
+use clipboard::ClipboardState;
+use external::AllowedHosts;
+use logger::LoggerState;
+use policy::CommandPolicyState;
 use pty::PtyManager;
+use tauri::Manager;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    logger::load_log_settings();
+    logger::prune_logs_on_startup();
 
     tauri::Builder::default()
         .manage(PtyManager::new())
+        .manage(AllowedHosts::load())
+        .manage(LoggerState::default())
+        .manage(ClipboardState::default())
+        .manage(CommandPolicyState::load())
+        .setup(|app| {
+            logger::start_log_flusher(app.handle().clone());
+            pty::start_heartbeat(app.handle().clone());
+            pty::start_idle_reaper(app.handle().clone());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             // PTY commands
             pty::spawn_shell,
+            pty::spawn_command,
+            pty::spawn_tmux_attach,
+            pty::spawn_sibling,
+            pty::get_default_shell,
+            pty::list_available_shells,
             pty::write_to_pty,
+            pty::write_to_pty_bytes,
+            pty::set_session_env,
+            pty::set_default_env,
+            pty::queue_startup_commands,
+            pty::get_env,
+            pty::run_and_capture,
+            pty::read_pty_buffer,
+            pty::search_scrollback,
+            pty::get_clean_scrollback,
             pty::resize_pty,
+            pty::resize_all_sessions,
+            pty::get_pty_size,
             pty::kill_pty,
+            pty::kill_all_sessions,
+            pty::signal_pty,
             pty::get_cwd,
+            pty::get_cwd_all,
+            pty::get_process_tree,
+            pty::list_sessions,
+            pty::session_health,
+            pty::session_child_alive,
+            pty::get_session_title,
+            pty::get_terminal_capabilities,
+            pty::is_audited,
+            pty::get_recent_exit_codes,
+            pty::get_command_marks,
+            pty::is_at_prompt,
+            pty::set_osc52_policy,
+            pty::pause_output,
+            pty::resume_output,
+            pty::set_session_attached,
+            pty::start_recording,
+            pty::stop_recording,
+            pty::replay_session,
+            pty::stop_replay,
             pty::get_system_info,
+            pty::get_disk_usage,
+            pty::get_free_space,
+            pty::get_directory_listing_detailed,
+            pty::read_file_for_context,
+            pty::get_running_processes,
+            pty::get_app_metrics,
             pty::list_directory,
+            pty::snapshot_directory,
+            pty::diff_directory_snapshot,
+            // Session layout
+            layout::save_session_layout,
+            layout::restore_session_layout,
             // Keychain commands
             keychain::store_api_key,
             keychain::get_api_key,
             keychain::delete_api_key,
+            keychain::list_api_key_providers,
+            keychain::list_api_keys,
+            keychain::migrate_api_key,
+            keychain::rename_service,
+            keychain::keychain_available,
+            keychain::export_keys,
+            keychain::import_keys,
+            keychain::unlock_api_keys,
             // Logger commands
             logger::write_log,
+            logger::set_output_preview_limit,
+            logger::write_log_batch,
             logger::get_log_entries,
             logger::get_log_dates,
+            logger::watch_log,
+            logger::unwatch_log,
+            logger::search_logs,
+            logger::prune_logs,
+            logger::get_log_stats,
+            logger::set_log_encryption,
+            logger::set_log_directory,
+            logger::set_log_timezone,
+            logger::set_log_partitioning,
+            logger::flush_logs,
+            logger::export_logs,
+            logger::classify_command_risk,
+            logger::analyze_paste,
+            logger::verify_log_chain,
+            logger::migrate_logs,
+            logger::set_redaction_exceptions,
+            // Shell history
+            history::get_shell_history,
+            // Git commands
+            git::get_git_status,
+            // Clipboard
+            clipboard::copy_to_clipboard,
+            clipboard::read_clipboard,
             // External actions
             external::open_external_url,
+            external::check_external_url,
+            external::set_allowed_hosts,
+            external::get_allowed_hosts,
+            external::reveal_in_file_manager,
+            // Command policy
+            policy::set_command_policy,
+            // Panic mode
+            panic_mode::panic_lock,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running AI Terminal");
+        .build(tauri::generate_context!())
+        .expect("error while building AI Terminal")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                pty::kill_all_sessions(app.clone());
+                if let Err(e) = app.state::<LoggerState>().flush() {
+                    log::warn!("Failed to flush audit log on exit: {}", e);
+                }
+            }
+        });
 }
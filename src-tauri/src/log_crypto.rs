@@ -0,0 +1,204 @@
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Known plaintext encrypted with the derived key so a wrong passphrase is
+/// rejected up front instead of silently producing unreadable entries.
+const CHECK_PLAINTEXT: &[u8] = b"ai-terminal audit log";
+
+/// Key derivation parameters persisted next to the logs. Holds no secrets.
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyParams {
+    salt: String,
+    check: String,
+}
+
+/// Encrypts and decrypts individual audit log lines.
+///
+/// Each line is `base64(nonce || ciphertext)` using XChaCha20-Poly1305 with
+/// a key derived from the passphrase by Argon2id.
+pub struct LogCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl LogCipher {
+    /// Derive the key for `passphrase`, creating the salt on first use,
+    /// i.e. when `params_path` doesn't exist.
+    ///
+    /// Fails if the passphrase doesn't match the one the salt was created
+    /// with, or if `params_path` exists but can't be read.
+    pub fn unlock(passphrase: &str, params_path: &Path) -> Result<Self, String> {
+        if passphrase.is_empty() {
+            return Err("Passphrase must not be empty".to_string());
+        }
+
+        match fs::read_to_string(params_path) {
+            Ok(contents) => {
+                let params: KeyParams = serde_json::from_str(&contents)
+                    .map_err(|e| format!("Failed to parse encryption settings: {}", e))?;
+                let salt = BASE64
+                    .decode(&params.salt)
+                    .map_err(|e| format!("Invalid encryption salt: {}", e))?;
+                let cipher = Self::derive(passphrase, &salt)?;
+                if cipher.decrypt_line(&params.check).as_deref() != Some(CHECK_PLAINTEXT) {
+                    return Err("Incorrect log encryption passphrase".to_string());
+                }
+                return Ok(cipher);
+            }
+            // Only a missing file means first use: replacing settings that
+            // exist but can't be read would orphan every encrypted line.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to read encryption settings: {}", e)),
+        }
+
+        let mut salt = [0u8; SALT_LEN];
+        getrandom::getrandom(&mut salt).map_err(|e| format!("Failed to generate salt: {}", e))?;
+        let cipher = Self::derive(passphrase, &salt)?;
+        let params = KeyParams {
+            salt: BASE64.encode(salt),
+            check: cipher.encrypt_bytes(CHECK_PLAINTEXT)?,
+        };
+        let json = serde_json::to_string_pretty(&params)
+            .map_err(|e| format!("Failed to serialize encryption settings: {}", e))?;
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options
+            .open(params_path)
+            .map_err(|e| format!("Failed to save encryption settings: {}", e))?;
+        file.write_all(json.as_bytes())
+            .map_err(|e| format!("Failed to save encryption settings: {}", e))?;
+
+        Ok(cipher)
+    }
+
+    fn derive(passphrase: &str, salt: &[u8]) -> Result<Self, String> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| format!("Failed to derive key: {}", e))?;
+        let cipher = XChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|e| format!("Failed to initialize cipher: {}", e))?;
+        Ok(Self { cipher })
+    }
+
+    /// Encrypt one log line.
+    pub fn encrypt_line(&self, line: &str) -> Result<String, String> {
+        self.encrypt_bytes(line.as_bytes())
+    }
+
+    /// Decrypt one log line, or `None` if it wasn't written with this key.
+    pub fn decrypt_line(&self, line: &str) -> Option<Vec<u8>> {
+        let raw = BASE64.decode(line.trim()).ok()?;
+        if raw.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = raw.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+
+    fn encrypt_bytes(&self, plaintext: &[u8]) -> Result<String, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|e| format!("Failed to generate nonce: {}", e))?;
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), plaintext)
+            .map_err(|_| "Failed to encrypt log entry".to_string())?;
+
+        let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(BASE64.encode(out))
+    }
+}
+
+/// Leads a blob from `seal_with_passphrase`, identifying the format.
+const SEALED_MAGIC: &[u8] = b"AITSEAL1";
+
+/// Encrypt `plaintext` under a key derived from `passphrase` with a fresh
+/// salt. The result is `magic || salt || nonce || ciphertext` and carries
+/// everything `open_with_passphrase` needs besides the passphrase.
+pub fn seal_with_passphrase(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    if passphrase.is_empty() {
+        return Err("Passphrase must not be empty".to_string());
+    }
+    let mut salt = [0u8; SALT_LEN];
+    getrandom::getrandom(&mut salt).map_err(|e| format!("Failed to generate salt: {}", e))?;
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|e| format!("Failed to generate nonce: {}", e))?;
+    let ciphertext = LogCipher::derive(passphrase, &salt)?
+        .cipher
+        .encrypt(XNonce::from_slice(&nonce), plaintext)
+        .map_err(|_| "Failed to encrypt data".to_string())?;
+
+    let mut out = Vec::with_capacity(SEALED_MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(SEALED_MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypt a blob from `seal_with_passphrase`. Fails on a wrong passphrase
+/// or a damaged blob without distinguishing the two.
+pub fn open_with_passphrase(passphrase: &str, sealed: &[u8]) -> Result<Vec<u8>, String> {
+    let rest = sealed
+        .strip_prefix(SEALED_MAGIC)
+        .filter(|rest| rest.len() >= SALT_LEN + NONCE_LEN)
+        .ok_or("Not an encrypted bundle")?;
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    LogCipher::derive(passphrase, salt)?
+        .cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Incorrect passphrase or corrupted bundle".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_data_opens_only_with_its_passphrase() {
+        let sealed = seal_with_passphrase("correct horse", b"secret").unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(
+            open_with_passphrase("correct horse", &sealed).unwrap(),
+            b"secret"
+        );
+        assert!(open_with_passphrase("wrong", &sealed).is_err());
+        assert!(open_with_passphrase("correct horse", &sealed[..20]).is_err());
+        assert!(seal_with_passphrase("", b"secret").is_err());
+    }
+
+    #[test]
+    fn unreadable_settings_are_not_replaced() {
+        let dir = std::env::temp_dir().join(format!("log-crypto-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let params_path = dir.join("encryption.json");
+
+        assert!(LogCipher::unlock("correct horse", &params_path).is_ok());
+        assert!(LogCipher::unlock("correct horse", &params_path).is_ok());
+        assert!(LogCipher::unlock("wrong", &params_path).is_err());
+
+        fs::write(&params_path, b"\xff\xfe").unwrap();
+        assert!(LogCipher::unlock("correct horse", &params_path).is_err());
+        assert_eq!(fs::read(&params_path).unwrap(), b"\xff\xfe");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
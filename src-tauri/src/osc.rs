@@ -0,0 +1,376 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+/// Maximum OSC payload we buffer before discarding the sequence.
+const MAX_OSC_LEN: usize = 64 * 1024;
+
+/// A complete OSC sequence (`ESC ] <command> ; <payload> ST`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OscSequence {
+    pub command: String,
+    pub payload: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Osc,
+    OscEscape,
+}
+
+/// Streaming OSC scanner for PTY output.
+///
+/// Sequences may be split across reads; state carries over between calls
+/// to `feed`. Output is only observed, never modified.
+#[derive(Debug)]
+pub struct OscParser {
+    state: State,
+    buf: Vec<u8>,
+    overflow: bool,
+}
+
+impl Default for OscParser {
+    fn default() -> Self {
+        Self {
+            state: State::Ground,
+            buf: Vec::new(),
+            overflow: false,
+        }
+    }
+}
+
+impl OscParser {
+    /// Scan a chunk of output and return any OSC sequences completed in it,
+    /// each with the offset in `bytes` just past its terminator.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<(usize, OscSequence)> {
+        let mut out = Vec::new();
+
+        for (i, &b) in bytes.iter().enumerate() {
+            match self.state {
+                State::Ground => {
+                    if b == 0x1b {
+                        self.state = State::Escape;
+                    }
+                }
+                State::Escape => {
+                    if b == b']' {
+                        self.buf.clear();
+                        self.overflow = false;
+                        self.state = State::Osc;
+                    } else if b != 0x1b {
+                        self.state = State::Ground;
+                    }
+                }
+                State::Osc => match b {
+                    // BEL terminator
+                    0x07 => {
+                        out.extend(self.finish().map(|seq| (i + 1, seq)));
+                        self.state = State::Ground;
+                    }
+                    0x1b => self.state = State::OscEscape,
+                    // CAN / SUB abort the sequence
+                    0x18 | 0x1a => self.state = State::Ground,
+                    _ => self.push(b),
+                },
+                State::OscEscape => {
+                    if b == b'\\' {
+                        // ST (ESC \) terminator
+                        out.extend(self.finish().map(|seq| (i + 1, seq)));
+                        self.state = State::Ground;
+                    } else if b == b']' {
+                        // A new OSC started before the previous one terminated.
+                        self.buf.clear();
+                        self.overflow = false;
+                        self.state = State::Osc;
+                    } else {
+                        self.state = State::Ground;
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    fn push(&mut self, b: u8) {
+        if self.buf.len() >= MAX_OSC_LEN {
+            self.overflow = true;
+            return;
+        }
+        self.buf.push(b);
+    }
+
+    fn finish(&mut self) -> Option<OscSequence> {
+        let raw = std::mem::take(&mut self.buf);
+        if std::mem::take(&mut self.overflow) {
+            return None;
+        }
+
+        let text = String::from_utf8_lossy(&raw);
+        let (command, payload) = match text.split_once(';') {
+            Some((command, payload)) => (command, payload),
+            None => (text.as_ref(), ""),
+        };
+        if command.is_empty() {
+            return None;
+        }
+
+        Some(OscSequence {
+            command: command.to_string(),
+            payload: payload.to_string(),
+        })
+    }
+}
+
+/// Parse an OSC 7 payload (`file://host/path`) into a local path.
+///
+/// Reports from other hosts (e.g. a shell inside `ssh`) are ignored since
+/// their paths don't exist locally.
+pub fn parse_osc7_cwd(payload: &str) -> Option<String> {
+    let url = url::Url::parse(payload).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    if let Some(host) = url.host_str() {
+        if !is_local_host(host) {
+            return None;
+        }
+    }
+    let path = percent_decode(url.path())?;
+    if path.starts_with('/') {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Parse an OSC 52 payload (`<selections>;<base64 text>`) into the text a
+/// program wants placed on the clipboard.
+///
+/// Returns `None` for clipboard queries (`?`), which we never answer since
+/// they would let programs read the user's clipboard, and for data that
+/// isn't valid base64-encoded UTF-8.
+pub fn parse_osc52_payload(payload: &str) -> Option<String> {
+    let (_selections, data) = payload.split_once(';')?;
+    if data == "?" {
+        return None;
+    }
+    let bytes = BASE64.decode(data).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// An OSC 133 shell-integration mark (the "FinalTerm" semantic prompt
+/// protocol).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptMark {
+    /// `A`: the prompt is about to be drawn.
+    PromptStart,
+    /// `B`: the prompt ended; the user is typing a command.
+    CommandStart,
+    /// `C`: the command was submitted and its output follows.
+    CommandExecuted,
+    /// `D[;<status>]`: the command finished, with its exit status if the
+    /// shell reported one.
+    CommandFinished(Option<i32>),
+}
+
+/// Parse an OSC 133 payload such as `A`, `C` or `D;1`. Extra `;key=value`
+/// options some shells append are ignored.
+pub fn parse_osc133_mark(payload: &str) -> Option<PromptMark> {
+    let mut fields = payload.split(';');
+    match fields.next()? {
+        "A" => Some(PromptMark::PromptStart),
+        "B" => Some(PromptMark::CommandStart),
+        "C" => Some(PromptMark::CommandExecuted),
+        "D" => Some(PromptMark::CommandFinished(
+            fields.next().and_then(|status| status.parse().ok()),
+        )),
+        _ => None,
+    }
+}
+
+/// Reduce terminal output to the text a user would see: drop escape
+/// sequences (CSI, OSC, DCS and two-byte escapes) and control characters
+/// other than newline and tab, and apply backspaces within a line.
+pub fn strip_escapes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates, then a final byte.
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, DCS, SOS, PM and APC run until BEL or ST.
+                Some(']' | 'P' | 'X' | '^' | '_') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // nF escapes such as `ESC ( B`: intermediates, then a final byte.
+                Some(c) if ('\x20'..='\x2f').contains(&c) => {
+                    while chars.next_if(|c| ('\x20'..='\x2f').contains(c)).is_some() {}
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\x08' => {
+                if !out.ends_with('\n') {
+                    out.pop();
+                }
+            }
+            '\n' | '\t' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn is_local_host(host: &str) -> bool {
+    if host.is_empty() || host.eq_ignore_ascii_case("localhost") {
+        return true;
+    }
+    let Some(local) = local_hostname() else {
+        return false;
+    };
+    let short = |h: &str| h.split('.').next().unwrap_or(h).to_ascii_lowercase();
+    host.eq_ignore_ascii_case(&local) || short(host) == short(&local)
+}
+
+#[cfg(unix)]
+fn local_hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if rc != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).to_string())
+}
+
+#[cfg(not(unix))]
+fn local_hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_osc52_clipboard_writes() {
+        assert_eq!(parse_osc52_payload("c;aGVsbG8="), Some("hello".to_string()));
+        assert_eq!(parse_osc52_payload(";"), Some(String::new()));
+        assert_eq!(parse_osc52_payload("c;?"), None);
+        assert_eq!(parse_osc52_payload("c;not base64!"), None);
+        assert_eq!(parse_osc52_payload("aGVsbG8="), None);
+    }
+
+    #[test]
+    fn parses_osc133_marks() {
+        assert_eq!(parse_osc133_mark("A"), Some(PromptMark::PromptStart));
+        assert_eq!(parse_osc133_mark("C"), Some(PromptMark::CommandExecuted));
+        assert_eq!(
+            parse_osc133_mark("D;127"),
+            Some(PromptMark::CommandFinished(Some(127)))
+        );
+        assert_eq!(
+            parse_osc133_mark("D;0;aid=42"),
+            Some(PromptMark::CommandFinished(Some(0)))
+        );
+        assert_eq!(
+            parse_osc133_mark("D"),
+            Some(PromptMark::CommandFinished(None))
+        );
+        assert_eq!(parse_osc133_mark("P;k=v"), None);
+    }
+
+    #[test]
+    fn strips_escapes_and_applies_backspaces() {
+        assert_eq!(
+            strip_escapes("\x1b[1;32mgit\x1b[0m statsu\x08\x08us\r\x1b]0;t\x07\x1bP+q\x1b\\"),
+            "git status"
+        );
+        assert_eq!(strip_escapes("a\tb\nc\x1b(B"), "a\tb\nc");
+        assert_eq!(strip_escapes("a\n\x08b"), "a\nb");
+    }
+
+    #[test]
+    fn parses_bel_and_st_terminated_sequences() {
+        let mut parser = OscParser::default();
+        let seqs = parser.feed(b"a\x1b]0;title\x07b\x1b]7;file:///tmp\x1b\\c");
+        assert_eq!(
+            seqs,
+            vec![
+                (
+                    11,
+                    OscSequence {
+                        command: "0".to_string(),
+                        payload: "title".to_string(),
+                    }
+                ),
+                (
+                    29,
+                    OscSequence {
+                        command: "7".to_string(),
+                        payload: "file:///tmp".to_string(),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn carries_sequences_across_reads() {
+        let mut parser = OscParser::default();
+        assert!(parser.feed(b"\x1b]2;ha").is_empty());
+        assert!(parser.feed(b"lf").is_empty());
+        let seqs = parser.feed(b"\x1b");
+        assert!(seqs.is_empty());
+        let seqs = parser.feed(b"\\");
+        assert_eq!(seqs.len(), 1);
+        assert_eq!(seqs[0].0, 1);
+        assert_eq!(seqs[0].1.payload, "half");
+    }
+
+    #[test]
+    fn decodes_osc7_paths() {
+        assert_eq!(
+            parse_osc7_cwd("file://localhost/Users/me/My%20Dir"),
+            Some("/Users/me/My Dir".to_string())
+        );
+        assert_eq!(parse_osc7_cwd("file:///tmp"), Some("/tmp".to_string()));
+        assert_eq!(parse_osc7_cwd("https://example.com/tmp"), None);
+        assert_eq!(parse_osc7_cwd("file://some-remote-host.invalid/srv"), None);
+    }
+}
@@ -0,0 +1,39 @@
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::logger::LoggerState;
+use crate::{keychain, pty};
+
+/// Payload of the `panic-triggered` event.
+#[derive(Debug, Clone, serde::Serialize)]
+struct PanicTriggered {
+    sessions_killed: usize,
+}
+
+/// Emergency stop: lock API keys, kill every session and flush the audit
+/// log. Returns the number of sessions killed.
+///
+/// `get_api_key` fails until `unlock_api_keys` is called or the app
+/// restarts. Emits `panic-triggered` `{ sessions_killed }` so the UI can
+/// show the locked state. This stops what is running now; anything a
+/// session already sent elsewhere can't be recalled.
+#[tauri::command(async)]
+pub fn panic_lock(app: AppHandle) -> usize {
+    // Lock first: killing sessions can take up to the SIGKILL grace period.
+    keychain::lock_api_keys();
+    let killed = pty::kill_all_sessions(app.clone());
+    if let Err(e) = app.state::<LoggerState>().flush() {
+        log::warn!("Failed to flush audit log after panic lock: {}", e);
+    }
+
+    log::warn!(
+        "Panic lock: killed {} session(s) and locked API keys",
+        killed
+    );
+    let _ = app.emit(
+        "panic-triggered",
+        PanicTriggered {
+            sessions_killed: killed,
+        },
+    );
+    killed
+}
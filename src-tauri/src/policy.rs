@@ -0,0 +1,260 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use tauri::State;
+
+use crate::config;
+use crate::logger::{program_name, program_words};
+use crate::osc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PolicyMode {
+    /// Only commands matching a pattern may run.
+    Allowlist,
+    /// Commands matching a pattern are blocked.
+    Denylist,
+}
+
+/// Commands `write_to_pty` lets through to the shell.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandPolicy {
+    pub mode: PolicyMode,
+    pub patterns: Vec<String>,
+}
+
+impl CommandPolicy {
+    /// Check a command line, returning why it's blocked if it is.
+    ///
+    /// The line is split into commands at `;`, `&`, `|`, newlines, parentheses
+    /// and backticks, ignoring quoting, and each is checked on its own. A
+    /// pattern matches a command equal to it or starting with it followed by
+    /// a space. `sudo`, `env` and similar wrappers with their options,
+    /// leading `VAR=value` assignments and program directories are ignored,
+    /// so `sudo -u root /bin/rm -rf x` matches `rm -rf`.
+    pub fn check(&self, line: &str) -> Result<(), String> {
+        for command in split_commands(line) {
+            let matched = self.patterns.iter().find(|pattern| {
+                let pattern = normalize(pattern);
+                !pattern.is_empty()
+                    && (command == pattern
+                        || command
+                            .strip_prefix(pattern.as_str())
+                            .is_some_and(|rest| rest.starts_with(' ')))
+            });
+            match (self.mode, matched) {
+                (PolicyMode::Allowlist, None) => {
+                    return Err(format!("{} is not in the command allowlist", command));
+                }
+                (PolicyMode::Denylist, Some(pattern)) => {
+                    return Err(format!("{} matches blocked pattern {}", command, pattern));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reduce a line of typed input, without its terminator, to the text the
+/// shell will run, with backspaces applied.
+///
+/// Fails for lines whose effect can't be known from the bytes: escape
+/// sequences (arrow keys, history recall), control characters other than
+/// backspace and DEL (Ctrl-R search, completion), and lines that had input
+/// but were erased to nothing.
+pub(crate) fn typed_line(raw: &[u8]) -> Result<String, String> {
+    if raw.iter().any(|&b| b < 0x20 && b != 0x08) {
+        return Err("Line contains control keys or escape sequences".to_string());
+    }
+    // Treat DEL like backspace so edits are applied.
+    let typed = String::from_utf8_lossy(raw).replace('\x7f', "\x08");
+    let text = osc::strip_escapes(&typed);
+    if text.is_empty() && !raw.is_empty() {
+        return Err("Line was erased before Enter".to_string());
+    }
+    Ok(text)
+}
+
+/// Split off a trailing line-continuation backslash, if `line` ends with an
+/// unescaped one.
+pub(crate) fn strip_continuation(line: &str) -> Option<&str> {
+    let trailing = line.len() - line.trim_end_matches('\\').len();
+    (trailing % 2 == 1).then(|| &line[..line.len() - 1])
+}
+
+/// Collapse whitespace and drop wrappers, assignments and the program's
+/// directory, leaving `program args...`.
+fn normalize(command: &str) -> String {
+    let words = program_words(command.split_whitespace().collect());
+    let mut normalized = Vec::with_capacity(words.len());
+    for (i, word) in words.into_iter().enumerate() {
+        normalized.push(if i == 0 { program_name(word) } else { word });
+    }
+    normalized.join(" ")
+}
+
+fn split_commands(line: &str) -> Vec<String> {
+    line.split([';', '&', '|', '\n', '(', ')', '`'])
+        .map(normalize)
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+/// The active command policy, if any. Persisted across launches.
+pub struct CommandPolicyState {
+    policy: Mutex<Option<CommandPolicy>>,
+}
+
+impl CommandPolicyState {
+    /// Load the persisted policy, treating a missing or invalid file as no
+    /// policy.
+    pub fn load() -> Self {
+        let policy = fs::read_to_string(get_command_policy_path())
+            .ok()
+            .and_then(|json| serde_json::from_str::<CommandPolicy>(&json).ok());
+        if let Some(policy) = &policy {
+            log::info!(
+                "Loaded command policy ({:?}, {} patterns)",
+                policy.mode,
+                policy.patterns.len()
+            );
+        }
+        Self {
+            policy: Mutex::new(policy),
+        }
+    }
+
+    pub(crate) fn current(&self) -> Option<CommandPolicy> {
+        self.policy.lock().clone()
+    }
+}
+
+impl Default for CommandPolicyState {
+    fn default() -> Self {
+        Self::load()
+    }
+}
+
+fn get_command_policy_path() -> PathBuf {
+    config::config_path("command_policy.json")
+}
+
+/// Restrict which commands `write_to_pty` sends to shells, for locked-down
+/// deployments. `mode` is `"allowlist"`, `"denylist"`, or `"off"` to remove
+/// the policy (`patterns` is then ignored). The policy is saved and applies
+/// to every session, including after a restart.
+///
+/// While a policy is set, `write_to_pty` holds input back until Enter so it
+/// can check the whole line: typed characters aren't echoed until then,
+/// and lines typed into interactive programs (editors, REPLs, password
+/// prompts) are checked too. Ctrl-C and Ctrl-D are passed through at once
+/// and discard the pending line. Lines continued with a trailing `\` are
+/// checked together once complete. Lines using arrow keys, history search,
+/// Tab or other control keys are blocked, since what they run can't be
+/// known from the input. `write_to_pty_bytes` bypasses the policy for raw or
+/// binary input.
+///
+/// A blocked line is dropped, the call fails with `permission_denied`, and a
+/// `pty-blocked` event `{ session_id, command, reason }` is emitted.
+#[tauri::command]
+pub fn set_command_policy(
+    state: State<'_, CommandPolicyState>,
+    mode: String,
+    patterns: Vec<String>,
+) -> Result<(), String> {
+    let mode = match mode.as_str() {
+        "allowlist" => PolicyMode::Allowlist,
+        "denylist" => PolicyMode::Denylist,
+        "off" => {
+            match fs::remove_file(get_command_policy_path()) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to remove command policy: {}", e)),
+            }
+            *state.policy.lock() = None;
+            log::info!("Removed command policy");
+            return Ok(());
+        }
+        other => return Err(format!("Unknown command policy mode: {}", other)),
+    };
+    let policy = CommandPolicy {
+        mode,
+        patterns: patterns
+            .iter()
+            .map(|p| p.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|p| !p.is_empty())
+            .collect(),
+    };
+
+    let json = serde_json::to_string_pretty(&policy)
+        .map_err(|e| format!("Failed to serialize command policy: {}", e))?;
+    config::write_private(&get_command_policy_path(), json.as_bytes())
+        .map_err(|e| format!("Failed to save command policy: {}", e))?;
+
+    log::info!(
+        "Set command policy ({:?}, {} patterns)",
+        policy.mode,
+        policy.patterns.len()
+    );
+    *state.policy.lock() = Some(policy);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(mode: PolicyMode, patterns: &[&str]) -> CommandPolicy {
+        CommandPolicy {
+            mode,
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn denylist_blocks_matching_commands_anywhere_in_the_line() {
+        let deny = policy(PolicyMode::Denylist, &["rm -rf", "shutdown"]);
+        assert!(deny.check("ls -la").is_ok());
+        assert!(deny.check("rm file").is_ok());
+        assert!(deny.check("rmdir -rf x").is_ok());
+        assert!(deny.check("rm -rf /tmp/x").is_err());
+        assert!(deny.check("cd /tmp && sudo /bin/rm  -rf x").is_err());
+        assert!(deny.check("sudo -u root rm -rf /").is_err());
+        assert!(deny.check("echo $(shutdown now)").is_err());
+        assert!(deny.check("").is_ok());
+    }
+
+    #[test]
+    fn rejects_lines_that_cant_be_checked() {
+        assert_eq!(typed_line(b"lx\x7fs -l").unwrap(), "ls -l");
+        assert_eq!(typed_line(b"").unwrap(), "");
+        // Up arrow, Ctrl-R history search and Tab completion.
+        assert!(typed_line(b"\x1b[A").is_err());
+        assert!(typed_line(b"\x12rm").is_err());
+        assert!(typed_line(b"rm -r\t").is_err());
+        // Input erased before Enter.
+        assert!(typed_line(b"x\x7f").is_err());
+    }
+
+    #[test]
+    fn finds_line_continuations() {
+        assert_eq!(strip_continuation("rm -rf \\"), Some("rm -rf "));
+        assert_eq!(strip_continuation("echo \\\\"), None);
+        assert_eq!(strip_continuation("echo \\\\\\"), Some("echo \\\\"));
+        assert_eq!(strip_continuation("ls"), None);
+    }
+
+    #[test]
+    fn allowlist_requires_every_command_to_match() {
+        let allow = policy(PolicyMode::Allowlist, &["ls", "git status"]);
+        assert!(allow.check("ls").is_ok());
+        assert!(allow.check("ls -la | ls").is_ok());
+        assert!(allow.check("git status --short").is_ok());
+        assert!(allow.check("git push").is_err());
+        assert!(allow.check("ls; curl evil.sh").is_err());
+        assert!(allow.check("lsof").is_err());
+        assert!(allow.check("   ").is_ok());
+    }
+}
@@ -0,0 +1,466 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Run a command to completion, killing it if it exceeds `timeout`.
+///
+/// Returns `Ok(None)` on timeout. Stdout and stderr are drained on helper
+/// threads so a chatty child can't block on a full pipe.
+pub fn output_with_timeout(
+    cmd: &mut Command,
+    timeout: Duration,
+) -> std::io::Result<Option<Output>> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let stdout_reader = thread::spawn(move || read_all(stdout));
+    let stderr_reader = thread::spawn(move || read_all(stderr));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let stdout = stdout_reader.join().unwrap_or_default();
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    Ok(status.map(|status| Output {
+        status,
+        stdout,
+        stderr,
+    }))
+}
+
+fn read_all<R: Read>(source: Option<R>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(mut source) = source {
+        let _ = source.read_to_end(&mut buf);
+    }
+    buf
+}
+
+/// One row of the system process table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessRow {
+    pub pid: u32,
+    pub ppid: u32,
+    pub name: String,
+    /// Recent CPU usage on macOS; average over the process's lifetime on
+    /// Linux (as `ps` reports it there).
+    pub cpu_percent: Option<f32>,
+    /// Resident set size.
+    pub memory_bytes: Option<u64>,
+}
+
+/// A process and its descendants, as returned by `get_process_tree`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessNode {
+    pub pid: u32,
+    pub name: String,
+    /// See `ProcessRow::cpu_percent`.
+    pub cpu_percent: Option<f32>,
+    /// Resident set size.
+    pub memory_bytes: Option<u64>,
+    pub children: Vec<ProcessNode>,
+}
+
+/// How long `ps` may take to list processes on macOS.
+#[cfg(target_os = "macos")]
+const PS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// List all processes via `ps`.
+#[cfg(target_os = "macos")]
+pub fn process_table() -> std::io::Result<Vec<ProcessRow>> {
+    let output = output_with_timeout(
+        Command::new("ps").args(["-axo", "pid=,ppid=,pcpu=,rss=,comm="]),
+        PS_TIMEOUT,
+    )?
+    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::TimedOut, "ps timed out"))?;
+    Ok(parse_ps_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// List all processes by walking `/proc`.
+#[cfg(target_os = "linux")]
+pub fn process_table() -> std::io::Result<Vec<ProcessRow>> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+    let uptime_secs: Option<f64> = std::fs::read_to_string("/proc/uptime")
+        .ok()
+        .and_then(|uptime| uptime.split_whitespace().next()?.parse().ok());
+    let mut rows = Vec::new();
+    for entry in std::fs::read_dir("/proc")?.flatten() {
+        // Only numbered entries are processes; `self` and `thread-self`
+        // link back to ours.
+        if entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+            .is_none()
+        {
+            continue;
+        }
+        let path = entry.path();
+        // Processes can exit while we walk; skip anything unreadable.
+        let Ok(stat) = std::fs::read_to_string(path.join("stat")) else {
+            continue;
+        };
+        let Some(stat) = parse_proc_stat(&stat) else {
+            continue;
+        };
+        let memory_bytes = std::fs::read_to_string(path.join("statm"))
+            .ok()
+            .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<u64>().ok())
+            .map(|pages| pages * page_size);
+        let cpu_percent = uptime_secs.and_then(|uptime| {
+            let elapsed = uptime - stat.start_ticks as f64 / ticks_per_sec;
+            (elapsed > 0.0)
+                .then(|| (stat.cpu_ticks as f64 / ticks_per_sec / elapsed * 100.0) as f32)
+        });
+        rows.push(ProcessRow {
+            pid: stat.pid,
+            ppid: stat.ppid,
+            name: stat.name,
+            cpu_percent,
+            memory_bytes,
+        });
+    }
+    Ok(rows)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub fn process_table() -> std::io::Result<Vec<ProcessRow>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "process listing is not supported on this platform",
+    ))
+}
+
+/// Resource usage of the app's own process, from `own_usage`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OwnUsage {
+    /// CPU used across all threads during the sample, as a percentage of
+    /// one core.
+    pub cpu_percent: Option<f32>,
+    /// Resident set size.
+    pub memory_bytes: Option<u64>,
+    pub thread_count: Option<u32>,
+    pub open_fds: Option<u32>,
+}
+
+/// Measure this process's CPU usage over `sample` (blocking for that long)
+/// and read its memory, thread and file descriptor counts.
+pub fn own_usage(sample: Duration) -> OwnUsage {
+    let cpu_before = cpu_time();
+    let started = Instant::now();
+    thread::sleep(sample);
+    let cpu_percent = cpu_before.zip(cpu_time()).map(|(before, after)| {
+        let wall = started.elapsed().as_secs_f64();
+        ((after.saturating_sub(before)).as_secs_f64() / wall * 100.0) as f32
+    });
+    OwnUsage {
+        cpu_percent,
+        ..own_counts()
+    }
+}
+
+/// User plus system CPU time consumed by this process so far.
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let duration = |tv: libc::timeval| {
+        Duration::new(tv.tv_sec.max(0) as u64, 0) + Duration::from_micros(tv.tv_usec.max(0) as u64)
+    };
+    Some(duration(usage.ru_utime) + duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+/// Memory, threads and open descriptors from `/proc/self`.
+#[cfg(target_os = "linux")]
+fn own_counts() -> OwnUsage {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+    let count = |dir: &str| {
+        std::fs::read_dir(dir)
+            .ok()
+            .map(|entries| entries.count() as u32)
+    };
+    OwnUsage {
+        memory_bytes: std::fs::read_to_string("/proc/self/statm")
+            .ok()
+            .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<u64>().ok())
+            .map(|pages| pages * page_size),
+        thread_count: count("/proc/self/task"),
+        // Less the descriptor `read_dir` itself holds open.
+        open_fds: count("/proc/self/fd").map(|n| n.saturating_sub(1)),
+        ..OwnUsage::default()
+    }
+}
+
+/// Memory and threads from `proc_pidinfo(PROC_PIDTASKINFO)` and open
+/// descriptors from `PROC_PIDLISTFDS`.
+#[cfg(target_os = "macos")]
+fn own_counts() -> OwnUsage {
+    let pid = std::process::id() as libc::c_int;
+    let mut info: libc::proc_taskinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+    let read = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDTASKINFO,
+            0,
+            &mut info as *mut _ as *mut libc::c_void,
+            size,
+        )
+    };
+    // With no buffer, PROC_PIDLISTFDS returns the bytes needed for the list.
+    let fd_bytes =
+        unsafe { libc::proc_pidinfo(pid, libc::PROC_PIDLISTFDS, 0, std::ptr::null_mut(), 0) };
+    OwnUsage {
+        memory_bytes: (read == size).then_some(info.pti_resident_size),
+        thread_count: (read == size).then_some(info.pti_threadnum.max(0) as u32),
+        open_fds: (fd_bytes > 0)
+            .then(|| (fd_bytes as usize / std::mem::size_of::<libc::proc_fdinfo>()) as u32),
+        ..OwnUsage::default()
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn own_counts() -> OwnUsage {
+    OwnUsage::default()
+}
+
+/// Check whether a process still exists, without signalling it. A process
+/// owned by another user counts as alive; PID 0 never does.
+#[cfg(unix)]
+pub fn is_process_alive(pid: u32) -> bool {
+    // PIDs past `i32::MAX` would wrap to negative and address a process group.
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid == 0 {
+        return false;
+    }
+    let rc = unsafe { libc::kill(pid, 0) };
+    rc == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+pub fn is_process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, GetLastError, ERROR_ACCESS_DENIED, STILL_ACTIVE,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    if pid == 0 {
+        return false;
+    }
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if handle.is_null() {
+        // Protected processes refuse even limited queries but do exist.
+        return unsafe { GetLastError() } == ERROR_ACCESS_DENIED;
+    }
+    let mut exit_code = 0u32;
+    let queried = unsafe { GetExitCodeProcess(handle, &mut exit_code) } != 0;
+    unsafe { CloseHandle(handle) };
+    // An exited process stays openable while handles to it remain.
+    !queried || exit_code == STILL_ACTIVE as u32
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn is_process_alive(pid: u32) -> bool {
+    pid != 0
+}
+
+/// Parse `ps -o pid=,ppid=,pcpu=,rss=,comm=` output. `rss` is in KB and
+/// `comm` may contain spaces.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_ps_output(text: &str) -> Vec<ProcessRow> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse().ok()?;
+            let ppid = fields.next()?.parse().ok()?;
+            let cpu_percent = fields.next()?.parse().ok();
+            let memory_bytes = fields.next()?.parse::<u64>().ok().map(|kb| kb * 1024);
+            let name = fields.collect::<Vec<_>>().join(" ");
+            // `comm` is the executable path on macOS; show just the name.
+            let name = name.rsplit('/').next().unwrap_or(&name).to_string();
+            Some(ProcessRow {
+                pid,
+                ppid,
+                name,
+                cpu_percent,
+                memory_bytes,
+            })
+        })
+        .collect()
+}
+
+/// Fields of `/proc/<pid>/stat` used for the process table.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct ProcStat {
+    pid: u32,
+    name: String,
+    ppid: u32,
+    /// User plus system CPU time, in clock ticks.
+    cpu_ticks: u64,
+    /// Start time after boot, in clock ticks.
+    start_ticks: u64,
+}
+
+/// Parse `/proc/<pid>/stat`. The name is wrapped in parentheses and may
+/// itself contain spaces or `)`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_stat(stat: &str) -> Option<ProcStat> {
+    let (pid, rest) = stat.split_once(" (")?;
+    let (name, fields) = rest.rsplit_once(") ")?;
+    // Fields after the name, starting from `state` (field 3 in proc(5)).
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let field = |index: usize| -> Option<u64> { fields.get(index)?.parse().ok() };
+    Some(ProcStat {
+        pid: pid.trim().parse().ok()?,
+        name: name.to_string(),
+        ppid: fields.get(1)?.parse().ok()?,
+        cpu_ticks: field(11)? + field(12)?,
+        start_ticks: field(19)?,
+    })
+}
+
+/// Build the tree of processes rooted at `root`, or `None` if it isn't in
+/// `rows`.
+pub fn build_tree(rows: &[ProcessRow], root: u32) -> Option<ProcessNode> {
+    let mut children: HashMap<u32, Vec<&ProcessRow>> = HashMap::new();
+    for row in rows {
+        if row.pid != row.ppid {
+            children.entry(row.ppid).or_default().push(row);
+        }
+    }
+
+    fn node(row: &ProcessRow, children: &HashMap<u32, Vec<&ProcessRow>>) -> ProcessNode {
+        let mut kids: Vec<ProcessNode> = children
+            .get(&row.pid)
+            .map(|kids| kids.iter().map(|kid| node(kid, children)).collect())
+            .unwrap_or_default();
+        kids.sort_by_key(|kid| kid.pid);
+        ProcessNode {
+            pid: row.pid,
+            name: row.name.clone(),
+            cpu_percent: row.cpu_percent,
+            memory_bytes: row.memory_bytes,
+            children: kids,
+        }
+    }
+
+    rows.iter()
+        .find(|row| row.pid == root)
+        .map(|row| node(row, &children))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(pid: u32, ppid: u32, name: &str) -> ProcessRow {
+        ProcessRow {
+            pid,
+            ppid,
+            name: name.to_string(),
+            cpu_percent: None,
+            memory_bytes: None,
+        }
+    }
+
+    #[test]
+    fn parses_ps_rows() {
+        let rows = parse_ps_output("  412     1   0.0  2048 /bin/zsh\n  900   412  12.5 10240 /usr/local/bin/Code Helper\nbad line\n");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].name, "zsh");
+        assert_eq!(rows[0].memory_bytes, Some(2048 * 1024));
+        assert_eq!(rows[1].ppid, 412);
+        assert_eq!(rows[1].cpu_percent, Some(12.5));
+        assert_eq!(rows[1].name, "Code Helper");
+    }
+
+    #[test]
+    fn parses_proc_stat_with_odd_names() {
+        let tail = "0 0 0 0 0 0 0 0 0 250 50 0 0 20 0 1 0 1000 0";
+        assert_eq!(
+            parse_proc_stat(&format!("1234 (tmux: server) S 1 {}", tail)),
+            Some(ProcStat {
+                pid: 1234,
+                name: "tmux: server".to_string(),
+                ppid: 1,
+                cpu_ticks: 300,
+                start_ticks: 1000,
+            })
+        );
+        let odd = parse_proc_stat(&format!("77 (a) b) R 42 {}", tail)).unwrap();
+        assert_eq!((odd.pid, odd.name.as_str(), odd.ppid), (77, "a) b", 42));
+        assert_eq!(parse_proc_stat("77 (short) R 42 77"), None);
+        assert_eq!(parse_proc_stat("garbage"), None);
+    }
+
+    #[test]
+    fn builds_descendant_tree() {
+        let rows = vec![
+            row(1, 0, "init"),
+            row(10, 1, "zsh"),
+            row(30, 10, "npm"),
+            row(20, 10, "vim"),
+            row(31, 30, "node"),
+            row(99, 1, "other"),
+        ];
+        let tree = build_tree(&rows, 10).unwrap();
+        assert_eq!(tree.name, "zsh");
+        let kids: Vec<u32> = tree.children.iter().map(|c| c.pid).collect();
+        assert_eq!(kids, vec![20, 30]);
+        assert_eq!(tree.children[1].children[0].name, "node");
+
+        assert!(build_tree(&rows, 31).unwrap().children.is_empty());
+        assert_eq!(build_tree(&rows, 5), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reports_own_usage_from_proc() {
+        let usage = own_usage(Duration::from_millis(10));
+        assert!(usage.cpu_percent.is_some());
+        assert!(usage.memory_bytes.unwrap() > 0);
+        assert!(usage.thread_count.unwrap() >= 1);
+        assert!(usage.open_fds.is_some());
+    }
+
+    #[test]
+    fn probes_process_liveness() {
+        assert!(is_process_alive(std::process::id()));
+        assert!(!is_process_alive(0));
+        // Beyond any PID the OS hands out.
+        assert!(!is_process_alive(0x7fff_fff0));
+        assert!(!is_process_alive(u32::MAX));
+    }
+}
@@ -1,442 +1,4910 @@
-use parking_lot::Mutex;
-use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize};
-use std::collections::HashMap;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use parking_lot::{Condvar, Mutex};
+use portable_pty::{CommandBuilder, PtySize};
+use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
 use std::thread;
+use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter, Manager};
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
+use crate::clipboard::ClipboardState;
+use crate::config;
+use crate::error::CommandError;
+use crate::external;
+use crate::logger::{redact_secrets, LoggerState};
+use crate::osc::{self, OscParser, PromptMark};
+use crate::policy::{self, CommandPolicy, CommandPolicyState};
+use crate::process::{self, ProcessNode};
+use crate::pty_backend::{NativeBackend, PtyBackend, PtyMaster};
+use crate::recording::{self, CastEvent, CastRecorder};
+use crate::system;
+
+/// Default scrollback retained per session (256 KB).
+const DEFAULT_SCROLLBACK_BYTES: usize = 256 * 1024;
+
+/// Output held back while a session is paused before the reader stops
+/// reading and lets the PTY apply backpressure to the child (1 MB).
+const PAUSED_OUTPUT_CAP: usize = 1024 * 1024;
+
+/// Exit codes kept per session for `get_recent_exit_codes`.
+const RECENT_EXIT_CODES: usize = 32;
+
+/// Commands kept per session for `get_command_marks`.
+const MAX_COMMAND_MARKS: usize = 1000;
+
 /// Represents an active PTY session.
 struct PtySession {
-    master: Box<dyn MasterPty + Send>,
+    master: Box<dyn PtyMaster>,
     writer: Box<dyn Write + Send>,
     child_id: u32,
     cwd: String,
+    /// Shell or, for `spawn_command` sessions, the program that was run.
+    shell: String,
+    /// `env_vars` the session was spawned with.
+    spawn_env: HashMap<String, String>,
+    /// Spawned without the app's environment.
+    clean_env: bool,
+    /// Variables injected after spawn via `set_session_env`.
+    env_overrides: HashMap<String, String>,
+    /// Last size applied to the PTY.
+    size: PtySize,
+    /// Size requested by `resize_pty` and not yet applied, with when it was
+    /// last requested.
+    pending_resize: Option<(PtySize, Instant)>,
+    /// Kill the session after this long without input or output.
+    idle_timeout: Option<Duration>,
+    /// Input held back by the command policy until Enter.
+    line_buffer: Vec<u8>,
+    /// Lines ending in a `\` continuation, held back by the command policy
+    /// until the command they start is complete.
+    continued_lines: Vec<u8>,
+    /// The command those lines add up to so far.
+    continued_command: String,
+    /// False for scratch sessions whose commands the user asked to keep
+    /// out of the audit log.
+    audited: bool,
+    /// NFC-normalize text from `write_to_pty`.
+    normalize_input: bool,
+    /// Set for `spawn_tmux_attach` sessions, which `kill_pty` detaches.
+    tmux_client: Option<TmuxClient>,
+    /// Commands from `queue_startup_commands` waiting for the first prompt.
+    /// `Some` while a thread is waiting to type them.
+    startup_commands: Option<Vec<String>>,
+    output: Arc<SessionOutput>,
 }
 
-/// Manages all PTY sessions.
-pub struct PtyManager {
-    sessions: Mutex<HashMap<String, Arc<Mutex<PtySession>>>>,
+/// Session state maintained by the reader thread.
+///
+/// Kept outside the session lock so the reader thread never contends with
+/// `write_to_pty`, which holds that lock while blocked on a full PTY.
+struct SessionOutput {
+    scrollback: Mutex<ScrollbackBuffer>,
+    /// Latest working directory reported by the shell via OSC 7.
+    reported_cwd: Mutex<Option<String>>,
+    /// The last report checked against the process's real cwd, and the cwd
+    /// chosen from the two. See `cached_cwd`.
+    checked_cwd: Mutex<Option<(String, String)>>,
+    /// Active asciinema recording, if any.
+    recorder: Mutex<Option<CastRecorder>>,
+    /// Latest title set via OSC 0/2.
+    title: Mutex<Option<String>>,
+    /// Set by `pause_output`; suppresses `pty-output` emits.
+    paused: AtomicBool,
+    flow: Mutex<OutputFlow>,
+    resumed: Condvar,
+    /// Cleared when the reader thread exits, including by panic.
+    reader_running: AtomicBool,
+    /// Output collected for `get_env` while it's waiting on `env`.
+    capture: Mutex<Option<Vec<u8>>>,
+    /// Last time output was read or input written, for the idle reaper.
+    last_activity: Mutex<Instant>,
+    /// Why the backend ended the session, reported in `pty-exit`.
+    exit_reason: Mutex<Option<&'static str>>,
+    /// Set once the session is killed or its program exits, so a reader
+    /// that times out waiting for output stops instead of waiting forever.
+    closing: AtomicBool,
+    /// How OSC 52 clipboard writes from the session are handled.
+    osc52_policy: Mutex<Osc52Policy>,
+    /// Statuses from OSC 133 `D` marks, oldest first.
+    exit_codes: Mutex<VecDeque<i32>>,
+    /// Command regions from OSC 133 marks, oldest first.
+    command_marks: Mutex<VecDeque<CommandMark>>,
+    started: Instant,
 }
 
-impl PtyManager {
-    pub fn new() -> Self {
-        Self {
-            sessions: Mutex::new(HashMap::new()),
-        }
+/// Clears `reader_running` when dropped, so it's reset even if the reader
+/// thread unwinds.
+struct ReaderRunningGuard(Arc<SessionOutput>);
+
+impl Drop for ReaderRunningGuard {
+    fn drop(&mut self) {
+        self.0.reader_running.store(false, Ordering::Release);
     }
 }
 
-impl Default for PtyManager {
-    fn default() -> Self {
-        Self::new()
-    }
+/// Where output goes next: the frontend, or a hold buffer while paused.
+struct OutputFlow {
+    held: Vec<u8>,
+    /// Output kept while detached by `set_session_attached`, bounded like
+    /// the scrollback.
+    detached: Option<ScrollbackBuffer>,
+    /// Partial trailing line of a `line_buffered` session.
+    lines: Option<LineBuffer>,
+    /// Taken by the reader thread when the PTY reaches EOF.
+    sink: Option<OutputSink>,
 }
 
-fn emit_pty_exit_once(app: &AppHandle, session_id: &str, exit_emitted: &AtomicBool) {
-    if !exit_emitted.swap(true, Ordering::AcqRel) {
-        let _ = app.emit("pty-exit", session_id);
-    }
+/// Most of an unterminated line a `line_buffered` session holds back.
+const LINE_HOLD_MAX: usize = 16 * 1024;
+
+/// Holds output back until a newline so it's delivered in whole lines.
+#[derive(Default)]
+struct LineBuffer {
+    partial: Vec<u8>,
 }
 
-#[cfg(unix)]
-fn terminate_pid(pid: u32) {
-    if pid == 0 {
-        return;
+impl LineBuffer {
+    /// Add output, returning everything up to and including its last
+    /// newline, or everything held once that exceeds `LINE_HOLD_MAX`.
+    fn push(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        self.partial.extend_from_slice(bytes);
+        if self.partial.len() > LINE_HOLD_MAX {
+            return Some(std::mem::take(&mut self.partial));
+        }
+        let end = self.partial.iter().rposition(|&b| b == b'\n')? + 1;
+        let rest = self.partial.split_off(end);
+        Some(std::mem::replace(&mut self.partial, rest))
     }
 
-    let pid_str = pid.to_string();
-    // Best-effort: SIGTERM then SIGKILL shortly after.
-    let _ = std::process::Command::new("kill")
-        .args(["-TERM", &pid_str])
-        .status();
+    /// Take the partial line held back, if any.
+    fn take(&mut self) -> Option<Vec<u8>> {
+        (!self.partial.is_empty()).then(|| std::mem::take(&mut self.partial))
+    }
+}
 
-    let _ = thread::spawn(move || {
-        thread::sleep(std::time::Duration::from_millis(750));
-        let _ = std::process::Command::new("kill")
-            .args(["-KILL", &pid_str])
-            .status();
-    });
+/// Session changes derived from OSC sequences that are surfaced as events.
+enum OscEvent {
+    Title(String),
+    /// OSC 52 clipboard write awaiting the user's consent.
+    ClipboardRequest(String),
+    /// OSC 52 clipboard write the session is allowed to make directly.
+    ClipboardWrite(String),
+    /// OSC 133 `D` ended a command that was run from a marked prompt.
+    CommandFinished {
+        command_text: Option<String>,
+        exit_code: Option<i32>,
+    },
 }
 
-#[cfg(not(unix))]
-fn terminate_pid(_pid: u32) {}
+/// Prompt, command and output regions of one command, from OSC 133 marks.
+///
+/// Offsets count bytes of output since the session started and point just
+/// past each mark; the scrollback holds the tail of that stream.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct CommandMark {
+    /// Start of the prompt (`A`).
+    pub prompt_start: u64,
+    /// Start of the command line the user typed (`B`).
+    pub command_start: Option<u64>,
+    /// Start of the command's output (`C`).
+    pub output_start: Option<u64>,
+    /// Where the command finished (`D`).
+    pub end: Option<u64>,
+    /// The command as echoed between `B` and `C`, without escape sequences.
+    pub command_text: Option<String>,
+    pub exit_code: Option<i32>,
+}
 
-/// Spawn a new PTY shell session and return the session ID.
-#[tauri::command]
-pub fn spawn_shell(
-    app: AppHandle,
-    rows: Option<u16>,
-    cols: Option<u16>,
-    cwd: Option<String>,
-    env_vars: Option<HashMap<String, String>>,
-) -> Result<String, String> {
-    let pty_system = native_pty_system();
-    let pty_rows = rows.unwrap_or(24);
-    let pty_cols = cols.unwrap_or(80);
-
-    let pair = pty_system
-        .openpty(PtySize {
-            rows: pty_rows,
-            cols: pty_cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| format!("Failed to open PTY: {}", e))?;
+/// What to do when a program asks to set the clipboard via OSC 52.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Osc52Policy {
+    Deny,
+    /// Emit `pty-clipboard-request` so the frontend can ask the user.
+    #[default]
+    Prompt,
+    Allow,
+}
+
+impl Osc52Policy {
+    fn parse(value: &str) -> Result<Self, CommandError> {
+        match value {
+            "deny" => Ok(Self::Deny),
+            "prompt" => Ok(Self::Prompt),
+            "allow" => Ok(Self::Allow),
+            other => Err(CommandError::InvalidInput(format!(
+                "Unknown OSC 52 policy: {}",
+                other
+            ))),
+        }
+    }
+}
 
-    let shell = select_shell();
-    let mut cmd = CommandBuilder::new(&shell);
-    cmd.arg("--login");
+impl SessionOutput {
+    fn new(scrollback_bytes: usize, sink: OutputSink, line_buffered: bool) -> Self {
+        Self {
+            scrollback: Mutex::new(ScrollbackBuffer::new(scrollback_bytes)),
+            reported_cwd: Mutex::new(None),
+            checked_cwd: Mutex::new(None),
+            recorder: Mutex::new(None),
+            title: Mutex::new(None),
+            paused: AtomicBool::new(false),
+            flow: Mutex::new(OutputFlow {
+                held: Vec::new(),
+                detached: None,
+                lines: line_buffered.then(LineBuffer::default),
+                sink: Some(sink),
+            }),
+            resumed: Condvar::new(),
+            reader_running: AtomicBool::new(true),
+            capture: Mutex::new(None),
+            last_activity: Mutex::new(Instant::now()),
+            exit_reason: Mutex::new(None),
+            closing: AtomicBool::new(false),
+            osc52_policy: Mutex::new(Osc52Policy::default()),
+            exit_codes: Mutex::new(VecDeque::new()),
+            command_marks: Mutex::new(VecDeque::new()),
+            started: Instant::now(),
+        }
+    }
 
-    let working_dir = cwd.unwrap_or_else(|| {
-        dirs::home_dir()
-            .map(|p| p.to_string_lossy().to_string())
-            .unwrap_or_else(|| "/".to_string())
-    });
-    cmd.cwd(&working_dir);
+    fn touch(&self) {
+        *self.last_activity.lock() = Instant::now();
+    }
 
-    if let Some(vars) = env_vars {
-        for (key, value) in vars {
-            cmd.env(key, value);
+    /// Forward output to the frontend, or hold it while paused.
+    ///
+    /// Once `PAUSED_OUTPUT_CAP` bytes are held this blocks the reader thread
+    /// until resumed, so the kernel PTY buffer fills and the child blocks on
+    /// write instead of output growing without bound.
+    fn deliver(&self, bytes: &[u8]) {
+        let mut flow = self.flow.lock();
+        let lines = match flow.lines.as_mut() {
+            Some(lines) => match lines.push(bytes) {
+                Some(lines) => lines,
+                None => return,
+            },
+            None => return self.pass_on(&mut flow, bytes),
+        };
+        self.pass_on(&mut flow, &lines);
+    }
+
+    /// Deliver the partial line a `line_buffered` session is holding back,
+    /// e.g. a prompt, once output pauses.
+    fn flush_partial_line(&self) {
+        let mut flow = self.flow.lock();
+        if let Some(partial) = flow.lines.as_mut().and_then(LineBuffer::take) {
+            self.pass_on(&mut flow, &partial);
         }
     }
-    cmd.env("TERM", "xterm-256color");
-    cmd.env("COLORTERM", "truecolor");
 
-    let child = pair
-        .slave
-        .spawn_command(cmd)
-        .map_err(|e| format!("Failed to spawn shell: {}", e))?;
+    fn pass_on(&self, flow: &mut parking_lot::MutexGuard<'_, OutputFlow>, bytes: &[u8]) {
+        if let Some(detached) = flow.detached.as_mut() {
+            detached.push(bytes);
+            return;
+        }
+        if self.paused.load(Ordering::Acquire) {
+            flow.held.extend_from_slice(bytes);
+            while self.paused.load(Ordering::Acquire) && flow.held.len() >= PAUSED_OUTPUT_CAP {
+                self.resumed.wait(flow);
+            }
+            return;
+        }
+        if let Some(sink) = flow.sink.as_mut() {
+            sink.send(bytes);
+        }
+    }
 
-    let child_id = child.process_id().unwrap_or(0);
-    let session_id = Uuid::new_v4().to_string();
+    /// Resume emitting, flushing held output first. Returns whether the
+    /// session was paused.
+    fn resume(&self) -> bool {
+        let mut flow = self.flow.lock();
+        let was_paused = self.paused.swap(false, Ordering::AcqRel);
+        let held = std::mem::take(&mut flow.held);
+        if !held.is_empty() {
+            if let Some(sink) = flow.sink.as_mut() {
+                sink.send(&held);
+            }
+        }
+        self.resumed.notify_all();
+        was_paused
+    }
 
-    let writer = pair
-        .master
-        .take_writer()
-        .map_err(|e| format!("Failed to get PTY writer: {}", e))?;
+    /// Stop emitting output, keeping up to `capacity` bytes of it for
+    /// `attach`. Returns `false` if already detached.
+    fn detach(&self, capacity: usize) -> bool {
+        let mut flow = self.flow.lock();
+        if flow.detached.is_some() {
+            return false;
+        }
+        flow.detached = Some(ScrollbackBuffer::new(capacity));
+        true
+    }
 
-    let mut reader = pair
-        .master
-        .try_clone_reader()
-        .map_err(|e| format!("Failed to get PTY reader: {}", e))?;
+    /// Emit output kept while detached as one chunk and resume emitting.
+    /// Returns `false` if the session wasn't detached.
+    fn attach(&self) -> bool {
+        let mut flow = self.flow.lock();
+        let Some(detached) = flow.detached.take() else {
+            return false;
+        };
+        let kept = detached.tail(usize::MAX);
+        if kept.is_empty() {
+            return true;
+        }
+        // Never block the caller: a paused session just holds it.
+        if self.paused.load(Ordering::Acquire) {
+            flow.held.extend_from_slice(&kept);
+        } else if let Some(sink) = flow.sink.as_mut() {
+            sink.send(&kept);
+        }
+        true
+    }
 
-    let session = Arc::new(Mutex::new(PtySession {
-        master: pair.master,
-        writer,
-        child_id,
-        cwd: working_dir,
-    }));
+    /// Flush held output and wait for the sink to drain; called at EOF.
+    fn finish_output(&self) {
+        self.flush_partial_line();
+        self.attach();
+        self.resume();
+        let sink = self.flow.lock().sink.take();
+        if let Some(sink) = sink {
+            sink.finish();
+        }
+    }
 
-    let state = app.state::<PtyManager>();
-    state
-        .sessions
-        .lock()
-        .insert(session_id.clone(), session.clone());
+    fn record_output(&self, bytes: &[u8]) {
+        let mut recorder = self.recorder.lock();
+        if let Some(active) = recorder.as_mut() {
+            if let Err(e) = active.output(bytes) {
+                log::warn!("Stopping recording after write failure: {}", e);
+                *recorder = None;
+            }
+        }
+    }
 
-    let exit_emitted = Arc::new(AtomicBool::new(false));
+    fn capture_output(&self, bytes: &[u8]) {
+        if let Some(captured) = self.capture.lock().as_mut() {
+            let room = ENV_CAPTURE_CAP.saturating_sub(captured.len());
+            captured.extend_from_slice(&bytes[..bytes.len().min(room)]);
+        }
+    }
 
-    // Spawn a reader thread that forwards PTY output to the frontend
-    let app_handle = app.clone();
-    let sid = session_id.clone();
-    let exit_emitted_reader = exit_emitted.clone();
-    let _ = thread::spawn(move || {
-        let mut buf = [0u8; 4096];
-        loop {
-            match reader.read(&mut buf) {
-                Ok(0) => {
-                    emit_pty_exit_once(&app_handle, &sid, exit_emitted_reader.as_ref());
-                    break;
+    /// Whether the shell has shown its first prompt: it has sent an OSC 133
+    /// mark, or, without shell integration, it's older than
+    /// `STARTUP_FALLBACK_DELAY` and has gone quiet.
+    fn reached_first_prompt(&self) -> bool {
+        !self.command_marks.lock().is_empty()
+            || (self.started.elapsed() >= STARTUP_FALLBACK_DELAY
+                && self.last_activity.lock().elapsed() >= STARTUP_QUIET)
+    }
+
+    /// Wait until `reached_first_prompt`, giving up after `timeout` if one
+    /// is given or once the session closes. Returns whether it was reached.
+    fn wait_for_first_prompt(&self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        while !self.reached_first_prompt() {
+            if self.closing.load(Ordering::Acquire)
+                || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return false;
+            }
+            thread::sleep(STARTUP_POLL);
+        }
+        true
+    }
+
+    fn stop_recording(&self) -> bool {
+        match self.recorder.lock().take() {
+            Some(active) => {
+                if let Err(e) = active.finish() {
+                    log::warn!("{}", e);
                 }
-                Ok(n) => {
-                    let data = String::from_utf8_lossy(&buf[..n]).to_string();
-                    #[derive(Clone, serde::Serialize)]
-                    struct PtyOutput {
-                        session_id: String,
-                        data: String,
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// React to an OSC sequence ending at stream `offset`, returning any
+    /// change the frontend should be told about.
+    fn handle_osc(&self, seq: &osc::OscSequence, offset: u64) -> Option<OscEvent> {
+        match seq.command.as_str() {
+            "7" => {
+                // Any program's output can contain OSC 7, so only keep
+                // reports of directories that exist here.
+                if let Some(cwd) = osc::parse_osc7_cwd(&seq.payload) {
+                    if std::path::Path::new(&cwd).is_dir() {
+                        *self.reported_cwd.lock() = Some(cwd);
                     }
-                    let _ = app_handle.emit(
-                        "pty-output",
-                        PtyOutput {
-                            session_id: sid.clone(),
-                            data,
-                        },
-                    );
                 }
-                Err(_) => {
-                    emit_pty_exit_once(&app_handle, &sid, exit_emitted_reader.as_ref());
-                    break;
+                None
+            }
+            // OSC 0 sets icon name + title, OSC 2 sets the title only.
+            "0" | "2" => {
+                let title: String = seq.payload.chars().filter(|c| !c.is_control()).collect();
+                let mut current = self.title.lock();
+                if current.as_deref() == Some(title.as_str()) {
+                    return None;
+                }
+                *current = Some(title.clone());
+                Some(OscEvent::Title(title))
+            }
+            "52" => {
+                let data = osc::parse_osc52_payload(&seq.payload)?;
+                match *self.osc52_policy.lock() {
+                    Osc52Policy::Deny => {
+                        log::info!("Ignored OSC 52 clipboard write (policy: deny)");
+                        None
+                    }
+                    Osc52Policy::Prompt => Some(OscEvent::ClipboardRequest(data)),
+                    Osc52Policy::Allow => Some(OscEvent::ClipboardWrite(data)),
                 }
             }
+            "133" => {
+                let mark = osc::parse_osc133_mark(&seq.payload)?;
+                self.apply_prompt_mark(mark, offset)
+            }
+            _ => None,
         }
+    }
 
-        // Clean up session
-        if let Some(manager) = app_handle.try_state::<PtyManager>() {
-            manager.sessions.lock().remove(&sid);
+    /// Update the command regions for an OSC 133 mark ending at `offset`.
+    fn apply_prompt_mark(&self, mark: PromptMark, offset: u64) -> Option<OscEvent> {
+        let mut marks = self.command_marks.lock();
+        match mark {
+            PromptMark::PromptStart => {
+                if marks.len() == MAX_COMMAND_MARKS {
+                    marks.pop_front();
+                }
+                marks.push_back(CommandMark {
+                    prompt_start: offset,
+                    ..CommandMark::default()
+                });
+                None
+            }
+            PromptMark::CommandStart => {
+                let current = marks.back_mut().filter(|m| m.end.is_none())?;
+                current.command_start = Some(offset);
+                None
+            }
+            PromptMark::CommandExecuted => {
+                let current = marks.back_mut().filter(|m| m.end.is_none())?;
+                current.output_start = Some(offset);
+                current.command_text = current.command_start.and_then(|start| {
+                    let echoed = self.scrollback.lock().range(start, offset)?;
+                    let text = osc::strip_escapes(&String::from_utf8_lossy(&echoed));
+                    let text = text.trim();
+                    (!text.is_empty()).then(|| text.to_string())
+                });
+                None
+            }
+            PromptMark::CommandFinished(exit_code) => {
+                if let Some(code) = exit_code {
+                    let mut exit_codes = self.exit_codes.lock();
+                    if exit_codes.len() == RECENT_EXIT_CODES {
+                        exit_codes.pop_front();
+                    }
+                    exit_codes.push_back(code);
+                }
+                let current = marks.back_mut().filter(|m| m.end.is_none())?;
+                current.end = Some(offset);
+                current.exit_code = exit_code;
+                current.output_start?;
+                Some(OscEvent::CommandFinished {
+                    command_text: current.command_text.clone(),
+                    exit_code,
+                })
+            }
         }
-    });
+    }
+}
 
-    // Wait for child exit in another thread
-    let app_handle2 = app.clone();
-    let sid2 = session_id.clone();
-    let exit_emitted_waiter = exit_emitted.clone();
-    let _ = thread::spawn(move || {
-        let mut child = child;
-        let _ = child.wait();
-        emit_pty_exit_once(&app_handle2, &sid2, exit_emitted_waiter.as_ref());
+/// Bounded ring buffer of raw PTY output, used to repaint the terminal
+/// after the frontend reconnects.
+struct ScrollbackBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+    /// Whether older output has been evicted to stay within `capacity`.
+    wrapped: bool,
+    /// Bytes pushed over the session's lifetime, i.e. the stream offset
+    /// just past the newest byte.
+    total: u64,
+}
 
-        if let Some(manager) = app_handle2.try_state::<PtyManager>() {
-            manager.sessions.lock().remove(&sid2);
+impl ScrollbackBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            data: VecDeque::with_capacity(capacity.min(64 * 1024)),
+            capacity,
+            wrapped: false,
+            total: 0,
         }
-    });
+    }
 
-    log::info!("Spawned PTY session: {} (PID: {})", session_id, child_id);
-    Ok(session_id)
-}
+    fn push(&mut self, bytes: &[u8]) {
+        self.total += bytes.len() as u64;
+        if self.capacity == 0 {
+            return;
+        }
+        let bytes = if bytes.len() > self.capacity {
+            self.wrapped = true;
+            &bytes[bytes.len() - self.capacity..]
+        } else {
+            bytes
+        };
+        let overflow = (self.data.len() + bytes.len()).saturating_sub(self.capacity);
+        self.wrapped |= overflow > 0;
+        self.data.drain(..overflow);
+        self.data.extend(bytes);
+    }
 
-fn select_shell() -> String {
-    let fallback = "/bin/zsh".to_string();
-    let raw = std::env::var("SHELL").unwrap_or_else(|_| fallback.clone());
-    // Only allow known system shells to avoid executing an unexpected binary.
-    match raw.as_str() {
-        "/bin/zsh" | "/bin/bash" | "/bin/sh" => raw,
-        _ => fallback,
+    /// Return up to `max_bytes` of the most recent output.
+    fn tail(&self, max_bytes: usize) -> Vec<u8> {
+        let skip = self.data.len().saturating_sub(max_bytes);
+        let mut out: Vec<u8> = self.data.iter().skip(skip).copied().collect();
+        // Don't start in the middle of a UTF-8 sequence.
+        let lead = out
+            .iter()
+            .take_while(|b| (**b & 0b1100_0000) == 0b1000_0000)
+            .count();
+        out.drain(..lead);
+        out
+    }
+
+    /// Bytes from `start` (or the oldest retained byte, if later) up to
+    /// `end`, and whether any of them had been evicted.
+    fn retained_range(&self, start: u64, end: u64) -> (Vec<u8>, bool) {
+        let first = self.total - self.data.len() as u64;
+        let bytes = self.range(start.max(first), end).unwrap_or_default();
+        (bytes, start < first)
+    }
+
+    /// Bytes between two stream offsets, or `None` if any of them have been
+    /// evicted.
+    fn range(&self, start: u64, end: u64) -> Option<Vec<u8>> {
+        let first = self.total - self.data.len() as u64;
+        if start < first || end > self.total || start > end {
+            return None;
+        }
+        let skip = (start - first) as usize;
+        let len = (end - start) as usize;
+        Some(self.data.iter().skip(skip).take(len).copied().collect())
     }
 }
 
-/// Write data to a PTY session.
-#[tauri::command]
-pub fn write_to_pty(app: AppHandle, session_id: String, data: String) -> Result<(), String> {
-    let state = app.state::<PtyManager>();
-    let sessions = state.sessions.lock();
-    let session = sessions
-        .get(&session_id)
-        .ok_or_else(|| format!("Session {} not found", session_id))?;
+/// How long a `spawn_shell` idempotency key maps to the session it created.
+const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(5);
 
-    let mut session_lock = session.lock();
-    session_lock
-        .writer
-        .write_all(data.as_bytes())
-        .map_err(|e| format!("Failed to write to PTY: {}", e))?;
-    session_lock
-        .writer
-        .flush()
-        .map_err(|e| format!("Failed to flush PTY writer: {}", e))?;
+/// Session created for an idempotency key, and when.
+type SpawnSlot = Arc<Mutex<Option<(String, Instant)>>>;
 
-    Ok(())
+/// Open sessions by ID. Shared with each session's threads so they can
+/// remove it on exit.
+type SessionMap = Arc<Mutex<HashMap<String, Arc<Mutex<PtySession>>>>>;
+
+/// Manages all PTY sessions.
+pub struct PtyManager {
+    sessions: SessionMap,
+    backend: Arc<dyn PtyBackend>,
+    /// Recent `spawn_shell` idempotency keys. Each slot is locked for the
+    /// duration of a spawn so concurrent calls with one key run one at a time.
+    recent_spawns: Mutex<HashMap<String, SpawnSlot>>,
+    /// Stop flags of running `replay_session` playbacks by synthetic ID.
+    replays: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    /// Variables from `set_default_env` given to every new session.
+    default_env: Mutex<HashMap<String, String>>,
 }
 
-/// Resize a PTY session.
-#[tauri::command]
-pub fn resize_pty(app: AppHandle, session_id: String, rows: u16, cols: u16) -> Result<(), String> {
-    let state = app.state::<PtyManager>();
-    let sessions = state.sessions.lock();
-    let session = sessions
-        .get(&session_id)
-        .ok_or_else(|| format!("Session {} not found", session_id))?;
+impl PtyManager {
+    pub fn new() -> Self {
+        let manager = Self::with_backend(Arc::new(NativeBackend::default()));
+        *manager.default_env.lock() = load_default_env();
+        manager
+    }
 
-    let session_lock = session.lock();
-    session_lock
-        .master
-        .resize(PtySize {
+    pub fn with_backend(backend: Arc<dyn PtyBackend>) -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            backend,
+            recent_spawns: Mutex::new(HashMap::new()),
+            replays: Arc::new(Mutex::new(HashMap::new())),
+            default_env: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the slot for `key`, dropping expired slots that aren't in use.
+    fn spawn_slot(&self, key: &str) -> SpawnSlot {
+        let mut recent = self.recent_spawns.lock();
+        recent.retain(|k, slot| {
+            k == key
+                || slot.try_lock().is_none_or(|entry| {
+                    entry
+                        .as_ref()
+                        .is_some_and(|(_, at)| at.elapsed() < IDEMPOTENCY_WINDOW)
+                })
+        });
+        recent.entry(key.to_string()).or_default().clone()
+    }
+
+    /// Look up a session, releasing the manager lock before returning.
+    fn get_session(&self, session_id: &str) -> Result<Arc<Mutex<PtySession>>, CommandError> {
+        self.sessions
+            .lock()
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| CommandError::session_not_found(session_id))
+    }
+
+    /// Whether commands from a session belong in the audit log. Unknown
+    /// sessions, e.g. ones that have already exited, are audited.
+    pub(crate) fn is_audited(&self, session_id: &str) -> bool {
+        match self.get_session(session_id) {
+            Ok(session) => session.lock().audited,
+            Err(_) => true,
+        }
+    }
+
+    /// Describe a session for `spawn_shell`.
+    fn spawned_session(&self, session_id: &str) -> Result<SpawnedSession, CommandError> {
+        let session = self.get_session(session_id)?;
+        let session = session.lock();
+        Ok(SpawnedSession {
+            session_id: session_id.to_string(),
+            child_id: session.child_id,
+            shell: session.shell.clone(),
+            cwd: session.cwd.clone(),
+            rows: session.size.rows,
+            cols: session.size.cols,
+        })
+    }
+
+    /// Open a PTY, start `request.program` in it, and register the session.
+    fn spawn(
+        &self,
+        events: Arc<dyn SessionEvents>,
+        request: SpawnRequest,
+    ) -> Result<String, CommandError> {
+        let SpawnRequest {
+            program,
+            args,
             rows,
             cols,
+            cwd,
+            env_vars,
+            scrollback_bytes,
+            encoding,
+            max_output_rate,
+            clean_env,
+            idle_timeout,
+            cwd_fallback,
+            audited,
+            normalize_input,
+            line_buffered,
+        } = request;
+        let working_dir = resolve_working_dir(cwd.as_deref(), cwd_fallback)?;
+        let size = PtySize {
+            rows: rows.unwrap_or(24),
+            cols: cols.unwrap_or(80),
             pixel_width: 0,
             pixel_height: 0,
-        })
-        .map_err(|e| format!("Failed to resize PTY: {}", e))?;
+        };
 
-    Ok(())
-}
+        let mut cmd = CommandBuilder::new(&program);
+        for arg in args {
+            cmd.arg(arg);
+        }
 
-/// Kill a PTY session.
-#[tauri::command]
-pub fn kill_pty(app: AppHandle, session_id: String) -> Result<(), String> {
-    let state = app.state::<PtyManager>();
-    let (pid, removed) = {
-        let mut sessions = state.sessions.lock();
-        let pid = sessions
-            .get(&session_id)
-            .map(|s| s.lock().child_id)
-            .unwrap_or(0);
-        let removed = sessions.remove(&session_id).is_some();
-        (pid, removed)
-    };
+        cmd.cwd(&working_dir);
+        let spawn_env = env_vars.unwrap_or_default();
+        apply_env(
+            &mut cmd,
+            clean_env,
+            &self.default_env.lock(),
+            spawn_env.clone(),
+        );
 
-    if !removed {
-        return Err(format!("Session {} not found", session_id));
-    }
+        let pty = self.backend.spawn(size, cmd)?;
+        let child = pty.child;
+        let mut reader = pty.reader;
 
-    terminate_pid(pid);
-    log::info!("Killed PTY session: {} (PID: {})", session_id, pid);
-    Ok(())
-}
+        let child_id = child.process_id().unwrap_or(0);
+        let session_id = Uuid::new_v4().to_string();
 
-/// Get the current working directory of a session.
-/// On macOS, queries the child process's actual CWD via lsof.
-/// Falls back to the stored initial CWD if lookup fails.
-#[tauri::command]
-pub fn get_cwd(app: AppHandle, session_id: String) -> Result<String, String> {
-    let state = app.state::<PtyManager>();
-    let sessions = state.sessions.lock();
-    let session = sessions
-        .get(&session_id)
-        .ok_or_else(|| format!("Session {} not found", session_id))?;
+        let emitter = OutputEmitter {
+            events: events.clone(),
+            session_id: session_id.clone(),
+            encoding,
+            decoder: Utf8Decoder::default(),
+            seq: 0,
+        };
+        let output = Arc::new(SessionOutput::new(
+            scrollback_bytes.unwrap_or(DEFAULT_SCROLLBACK_BYTES),
+            OutputSink::new(emitter, max_output_rate.unwrap_or(0)),
+            line_buffered,
+        ));
 
-    let session_lock = session.lock();
-    let pid = session_lock.child_id;
-    let fallback = session_lock.cwd.clone();
+        let session = Arc::new(Mutex::new(PtySession {
+            master: pty.master,
+            writer: pty.writer,
+            child_id,
+            cwd: working_dir,
+            shell: program,
+            spawn_env,
+            clean_env,
+            env_overrides: HashMap::new(),
+            size,
+            pending_resize: None,
+            idle_timeout,
+            line_buffer: Vec::new(),
+            continued_lines: Vec::new(),
+            continued_command: String::new(),
+            audited,
+            normalize_input,
+            tmux_client: None,
+            startup_commands: None,
+            output: output.clone(),
+        }));
 
-    // Try to get the real CWD from the child process
-    if pid > 0 {
-        if let Some(real_cwd) = get_process_cwd(pid) {
-            return Ok(real_cwd);
-        }
-    }
-    Ok(fallback)
-}
+        self.sessions
+            .lock()
+            .insert(session_id.clone(), session.clone());
 
-/// Query the actual CWD of a process.
-/// On macOS: uses `lsof -a -p <pid> -d cwd -Fn`
-/// On Linux: reads `/proc/<pid>/cwd` symlink
-fn get_process_cwd(pid: u32) -> Option<String> {
-    #[cfg(target_os = "macos")]
-    {
-        let output = std::process::Command::new("lsof")
-            .args(["-a", "-p", &pid.to_string(), "-d", "cwd", "-Fn"])
-            .output()
-            .ok()?;
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        // lsof output format: lines starting with 'n' contain the path
-        for line in stdout.lines() {
-            if let Some(path) = line.strip_prefix('n') {
-                if !path.is_empty() && path.starts_with('/') {
-                    return Some(path.to_string());
+        // Signalled once the reader has delivered all output, so `pty-exit`
+        // follows it.
+        let (drained_tx, drained_rx) = mpsc::channel::<()>();
+
+        // Spawn a reader thread that forwards PTY output to the frontend
+        let events_reader = events.clone();
+        let sessions_reader = self.sessions.clone();
+        let sid = session_id.clone();
+        let output_reader = output.clone();
+        let _ = thread::spawn(move || {
+            let _running = ReaderRunningGuard(output_reader.clone());
+            let mut buf = [0u8; 4096];
+            let mut osc_parser = OscParser::default();
+            loop {
+                let n = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    // A non-blocking backend had no output within its poll
+                    // interval; anything still buffered has been read.
+                    Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                        if output_reader.closing.load(Ordering::Acquire) {
+                            break;
+                        }
+                        output_reader.flush_partial_line();
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+
+                output_reader.touch();
+                let chunk_start = {
+                    let mut scrollback = output_reader.scrollback.lock();
+                    let start = scrollback.total;
+                    scrollback.push(&buf[..n]);
+                    start
+                };
+                output_reader.record_output(&buf[..n]);
+                output_reader.capture_output(&buf[..n]);
+                for (end, seq) in osc_parser.feed(&buf[..n]) {
+                    let event = match output_reader.handle_osc(&seq, chunk_start + end as u64) {
+                        Some(OscEvent::Title(title)) => SessionEvent::Title(PtyTitle {
+                            session_id: sid.clone(),
+                            title,
+                        }),
+                        Some(OscEvent::ClipboardRequest(data)) => {
+                            SessionEvent::ClipboardRequest(PtyClipboardRequest {
+                                session_id: sid.clone(),
+                                data,
+                            })
+                        }
+                        Some(OscEvent::ClipboardWrite(data)) => SessionEvent::ClipboardWrite(data),
+                        Some(OscEvent::CommandFinished {
+                            command_text,
+                            exit_code,
+                        }) => SessionEvent::Command(PtyCommand {
+                            session_id: sid.clone(),
+                            command_text,
+                            exit_code,
+                        }),
+                        None => continue,
+                    };
+                    events_reader.send(event);
                 }
+                output_reader.deliver(&buf[..n]);
             }
-        }
-        None
-    }
-    #[cfg(target_os = "linux")]
-    {
-        std::fs::read_link(format!("/proc/{}/cwd", pid))
-            .ok()
-            .map(|p| p.to_string_lossy().to_string())
-    }
-    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
-    {
-        let _ = pid;
-        None
-    }
-}
 
-/// Get system information for AI context.
-#[tauri::command]
-pub fn get_system_info() -> Result<serde_json::Value, String> {
-    let shell = std::env::var("SHELL").unwrap_or_else(|_| "unknown".to_string());
-    let home = dirs::home_dir()
-        .map(|p| p.to_string_lossy().to_string())
-        .unwrap_or_else(|| "unknown".to_string());
-    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            // Deliver any held or coalesced output; the waiter thread then
+            // announces the exit.
+            output_reader.finish_output();
+            let _ = drained_tx.send(());
 
-    let arch = if cfg!(target_arch = "aarch64") {
-        "arm64"
-    } else {
-        "x86_64"
-    };
+            // Clean up session
+            output_reader.stop_recording();
+            sessions_reader.lock().remove(&sid);
+        });
 
-    Ok(serde_json::json!({
-        "os": "macOS",
-        "arch": arch,
-        "shell": shell,
-        "home": home,
-        "user": user,
-        "term": "xterm-256color",
-    }))
-}
+        // Wait for child exit in another thread
+        let sessions_waiter = self.sessions.clone();
+        let output_waiter = output.clone();
+        let sid2 = session_id.clone();
+        let _ = thread::spawn(move || {
+            let mut child = child;
+            let status = child.wait().ok();
+            output_waiter.closing.store(true, Ordering::Release);
+            // Unblock a reader stalled on a full pause buffer so it can drain to EOF.
+            output_waiter.resume();
+            // A process that inherited the PTY can keep it open; don't wait
+            // on it indefinitely.
+            let _ = drained_rx.recv_timeout(READER_DRAIN_TIMEOUT);
+            let reason = *output_waiter.exit_reason.lock();
+            emit_pty_exit(events.as_ref(), &sid2, status.as_ref(), reason);
 
-/// List files and directories in a given path for autocomplete.
-/// Returns entries with name, path, and whether they are a directory.
-#[tauri::command]
-pub fn list_directory(path: String) -> Result<serde_json::Value, String> {
-    use std::path::Path;
+            sessions_waiter.lock().remove(&sid2);
+            output_waiter.stop_recording();
+        });
 
-    // Hide dotfiles by default unless the user explicitly typed a dot prefix.
-    // We infer this from the last path component in the *typed* string.
-    let typed = path.trim_end_matches('/');
-    let last_component = typed.rsplit('/').next().unwrap_or(typed);
-    let show_hidden = last_component.starts_with('.');
+        log::info!("Spawned PTY session: {} (PID: {})", session_id, child_id);
+        Ok(session_id)
+    }
 
-    let target = if let Some(stripped) = path.strip_prefix('~') {
-        let home = dirs::home_dir().ok_or_else(|| "Cannot resolve home directory".to_string())?;
-        home.join(stripped.trim_start_matches('/'))
-    } else {
-        Path::new(&path).to_path_buf()
-    };
+    /// Write bytes verbatim to a session's PTY and flush.
+    fn write(&self, session_id: &str, data: &[u8]) -> Result<(), CommandError> {
+        let session = self.get_session(session_id)?;
 
-    if !target.exists() {
-        return Ok(serde_json::json!({ "entries": [], "path": path }));
-    }
+        let mut session_lock = session.lock();
+        session_lock.output.touch();
+        session_lock
+            .writer
+            .write_all(data)
+            .map_err(|e| CommandError::io("Failed to write to PTY", e))?;
+        session_lock
+            .writer
+            .flush()
+            .map_err(|e| CommandError::io("Failed to flush PTY writer", e))?;
 
-    if !target.is_dir() {
-        return Ok(serde_json::json!({ "entries": [], "path": path }));
+        Ok(())
     }
 
-    struct DirEntry {
-        name: String,
-        name_lower: String,
-        path: String,
-        is_dir: bool,
+    /// `data` as the session wants it typed: in Unicode NFC if it was
+    /// spawned with `normalize_input`, otherwise unchanged.
+    fn normalized_input(&self, session_id: &str, data: String) -> Result<String, CommandError> {
+        if self.get_session(session_id)?.lock().normalize_input {
+            Ok(data.nfc().collect())
+        } else {
+            Ok(data)
+        }
     }
 
-    let mut entries: Vec<DirEntry> = Vec::new();
+    /// Write input line by line, sending each completed line only if
+    /// `policy` allows it. Returns the first blocked command and the reason;
+    /// it and anything after it in `data` are dropped.
+    ///
+    /// Lines ending in a `\` continuation are held until the command is
+    /// complete and checked as a whole. Lines `policy::typed_line` can't
+    /// reduce to plain text are blocked.
+    fn write_checked(
+        &self,
+        session_id: &str,
+        data: &[u8],
+        policy: &CommandPolicy,
+    ) -> Result<Option<(String, String)>, CommandError> {
+        let session = self.get_session(session_id)?;
 
-    match std::fs::read_dir(&target) {
-        Ok(read_dir) => {
-            for entry in read_dir.flatten() {
-                let name = entry.file_name().to_string_lossy().to_string();
-                if !show_hidden && name.starts_with('.') {
-                    continue;
+        let mut session_lock = session.lock();
+        session_lock.output.touch();
+        let mut allowed = Vec::new();
+        let mut blocked = None;
+        for &b in data {
+            match b {
+                b'\r' | b'\n' => {
+                    let line = std::mem::take(&mut session_lock.line_buffer);
+                    let text = match policy::typed_line(&line) {
+                        Ok(text) => text,
+                        Err(reason) => {
+                            session_lock.continued_lines.clear();
+                            session_lock.continued_command.clear();
+                            let shown = osc::strip_escapes(&String::from_utf8_lossy(&line));
+                            blocked = Some((shown.trim().to_string(), reason));
+                            break;
+                        }
+                    };
+                    session_lock.continued_lines.extend_from_slice(&line);
+                    session_lock.continued_lines.push(b);
+                    if let Some(start) = policy::strip_continuation(&text) {
+                        session_lock.continued_command.push_str(start);
+                        continue;
+                    }
+                    let mut command = std::mem::take(&mut session_lock.continued_command);
+                    command.push_str(&text);
+                    let lines = std::mem::take(&mut session_lock.continued_lines);
+                    let command = command.trim().to_string();
+                    if let Err(reason) = policy.check(&command) {
+                        blocked = Some((command, reason));
+                        break;
+                    }
+                    allowed.extend_from_slice(&lines);
                 }
-                // Skip hidden files unless the user explicitly typed a dot prefix
-                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
-                let full_path = entry.path().to_string_lossy().to_string();
-
-                entries.push(DirEntry {
-                    name_lower: name.to_lowercase(),
-                    name,
-                    path: full_path,
-                    is_dir,
-                });
+                // Ctrl-C / Ctrl-D act immediately and discard the pending line.
+                0x03 | 0x04 => {
+                    session_lock.line_buffer.clear();
+                    session_lock.continued_lines.clear();
+                    session_lock.continued_command.clear();
+                    allowed.push(b);
+                }
+                _ => session_lock.line_buffer.push(b),
             }
         }
-        Err(e) => {
-            return Err(format!("Failed to read directory: {}", e));
-        }
+
+        session_lock
+            .writer
+            .write_all(&allowed)
+            .map_err(|e| CommandError::io("Failed to write to PTY", e))?;
+        session_lock
+            .writer
+            .flush()
+            .map_err(|e| CommandError::io("Failed to flush PTY writer", e))?;
+
+        Ok(blocked)
     }
 
-    // Sort: directories first, then alphabetically
-    entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-        (true, false) => std::cmp::Ordering::Less,
-        (false, true) => std::cmp::Ordering::Greater,
-        _ => a.name_lower.cmp(&b.name_lower),
-    });
+    fn resize(&self, session_id: &str, rows: u16, cols: u16) -> Result<(), CommandError> {
+        let session = self.get_session(session_id)?;
+        let mut session_lock = session.lock();
+        resize_session(&mut session_lock, rows, cols)
+    }
 
-    let json_entries: Vec<serde_json::Value> = entries
-        .into_iter()
-        .map(|e| {
-            serde_json::json!({
-                "name": e.name,
-                "path": e.path,
-                "isDir": e.is_dir,
-            })
+    /// Resize once no further request has arrived for `RESIZE_DEBOUNCE`.
+    ///
+    /// Only the latest size is applied. A background thread waits out the
+    /// quiet period, so the final size lands even if requests stop abruptly.
+    fn resize_debounced(&self, session_id: &str, rows: u16, cols: u16) -> Result<(), CommandError> {
+        let session = self.get_session(session_id)?;
+        let size = PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        let scheduled = session
+            .lock()
+            .pending_resize
+            .replace((size, Instant::now()))
+            .is_some();
+        if !scheduled {
+            thread::spawn(move || apply_debounced_resize(session));
+        }
+        Ok(())
+    }
+
+    fn prompt_state(&self, session_id: &str) -> Result<PromptState, CommandError> {
+        let session = self.get_session(session_id)?;
+        let (foreground_pid, child_id, output) = {
+            let session_lock = session.lock();
+            (
+                session_lock.master.foreground_pid(),
+                session_lock.child_id,
+                session_lock.output.clone(),
+            )
+        };
+        let from_marks = output
+            .command_marks
+            .lock()
+            .back()
+            .map(|mark| mark.output_start.is_none() || mark.end.is_some());
+        let at_prompt =
+            from_marks.unwrap_or_else(|| child_id != 0 && foreground_pid == Some(child_id));
+        Ok(PromptState {
+            at_prompt,
+            foreground_pid,
         })
-        .collect();
+    }
 
-    Ok(serde_json::json!({
-        "entries": json_entries,
-        "path": target.to_string_lossy().to_string(),
-    }))
+    /// Type `commands` into a session once it first reaches its prompt,
+    /// calling `on_run` with each one written. See `queue_startup_commands`.
+    fn queue_startup_commands(
+        &self,
+        session_id: &str,
+        commands: Vec<String>,
+        allow_late: bool,
+        on_run: Arc<dyn Fn(&str) + Send + Sync>,
+    ) -> Result<(), CommandError> {
+        if let Some(command) = commands
+            .iter()
+            .find(|c| c.trim().is_empty() || c.contains(|ch: char| ch.is_control() && ch != '\t'))
+        {
+            return Err(CommandError::InvalidInput(format!(
+                "Startup commands must be single non-empty lines without control characters: {:?}",
+                command
+            )));
+        }
+
+        let session = self.get_session(session_id)?;
+        let mut session_lock = session.lock();
+        if let Some(queued) = session_lock.startup_commands.as_mut() {
+            queued.extend(commands);
+            return Ok(());
+        }
+        if !allow_late && session_lock.output.reached_first_prompt() {
+            return Err(CommandError::InvalidState(
+                "Session has already reached its first prompt".to_string(),
+            ));
+        }
+        session_lock.startup_commands = Some(commands);
+        drop(session_lock);
+
+        let session_id = session_id.to_string();
+        thread::spawn(move || run_startup_commands(session, &session_id, on_run.as_ref()));
+        Ok(())
+    }
+
+    /// Play back `.cast` output events under a new synthetic session ID,
+    /// `speed` times faster than recorded, then emit `pty-exit`.
+    fn replay(&self, events: Arc<dyn SessionEvents>, cast: Vec<CastEvent>, speed: f64) -> String {
+        let replay_id = format!("replay-{}", Uuid::new_v4());
+        let stopped = Arc::new(AtomicBool::new(false));
+        self.replays
+            .lock()
+            .insert(replay_id.clone(), stopped.clone());
+
+        let replays = self.replays.clone();
+        let id = replay_id.clone();
+        thread::spawn(move || {
+            let started = Instant::now();
+            let mut seq = 0;
+            for CastEvent(time, kind, data) in cast {
+                if kind != "o" || data.is_empty() {
+                    continue;
+                }
+                let due = Duration::try_from_secs_f64(time / speed).unwrap_or(Duration::MAX);
+                while !stopped.load(Ordering::Acquire) && started.elapsed() < due {
+                    thread::sleep(due.saturating_sub(started.elapsed()).min(REPLAY_POLL));
+                }
+                if stopped.load(Ordering::Acquire) {
+                    break;
+                }
+                seq += 1;
+                events.send(SessionEvent::Output(PtyOutput {
+                    session_id: id.clone(),
+                    data,
+                    encoding: OutputEncoding::Utf8.as_str(),
+                    seq,
+                }));
+            }
+
+            replays.lock().remove(&id);
+            let stopped = stopped.load(Ordering::Acquire);
+            events.send(SessionEvent::Exit(PtyExit {
+                session_id: id.clone(),
+                exit_code: (!stopped).then_some(0),
+                signal: None,
+                reason: Some(if stopped {
+                    "replay_stopped"
+                } else {
+                    "replay_finished"
+                }),
+            }));
+            log::info!("Finished replay {}", id);
+        });
+        replay_id
+    }
+
+    fn stop_replay(&self, replay_id: &str) -> Result<(), CommandError> {
+        let replays = self.replays.lock();
+        let stopped = replays
+            .get(replay_id)
+            .ok_or_else(|| CommandError::session_not_found(replay_id))?;
+        stopped.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Type `command` into a session and wait up to `timeout` for it to
+    /// finish, returning its output. See `run_and_capture`.
+    fn run_and_capture(
+        &self,
+        session_id: &str,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<CapturedOutput, CommandError> {
+        if command.contains(['\r', '\n']) {
+            return Err(CommandError::InvalidInput(
+                "Command must be a single line".to_string(),
+            ));
+        }
+        let session = self.get_session(session_id)?;
+        let (shell, output) = {
+            let session_lock = session.lock();
+            (session_lock.shell.clone(), session_lock.output.clone())
+        };
+        if output.scrollback.lock().capacity == 0 {
+            return Err(CommandError::InvalidState(
+                "Session has no scrollback to capture output from".to_string(),
+            ));
+        }
+        let integrated = !output.command_marks.lock().is_empty();
+        if integrated && !self.prompt_state(session_id)?.at_prompt {
+            return Err(CommandError::InvalidState(
+                "Session is running another command".to_string(),
+            ));
+        }
+        if !integrated && !is_posix_shell(&shell) {
+            return Err(CommandError::NotSupported(format!(
+                "Capturing output needs shell integration or a POSIX shell, not {}",
+                shell
+            )));
+        }
+
+        // Printed in two pieces so the echoed command line doesn't match.
+        let id = Uuid::new_v4().simple().to_string();
+        let sentinel = format!("__ait_done_{}:", id);
+        let input = if integrated {
+            format!("{}\n", command)
+        } else {
+            format!(
+                "{}; printf '\\n%s%s:%d\\n' __ait_ done_{} \"$?\"\n",
+                command, id
+            )
+        };
+
+        let start = output.scrollback.lock().total;
+        self.write(session_id, input.as_bytes())?;
+
+        let started = Instant::now();
+        loop {
+            if integrated {
+                let finished = output
+                    .command_marks
+                    .lock()
+                    .iter()
+                    .rev()
+                    .find(|mark| mark.output_start.is_some_and(|o| o >= start))
+                    .and_then(|mark| Some((mark.output_start?, mark.end?, mark.exit_code)));
+                if let Some((output_start, end, exit_code)) = finished {
+                    let (bytes, truncated) =
+                        output.scrollback.lock().retained_range(output_start, end);
+                    return Ok(CapturedOutput::new(&bytes, exit_code, false, truncated));
+                }
+            } else {
+                let (bytes, truncated) = {
+                    let scrollback = output.scrollback.lock();
+                    scrollback.retained_range(start, scrollback.total)
+                };
+                let text = String::from_utf8_lossy(&bytes);
+                if let Some(at) = text.find(&sentinel) {
+                    let exit_code = text[at + sentinel.len()..]
+                        .split(|c: char| !c.is_ascii_digit())
+                        .next()
+                        .and_then(|code| code.parse().ok());
+                    // Skip the echoed command line unless it was evicted.
+                    let body = &text[..at];
+                    let body = match (truncated, body.split_once('\n')) {
+                        (false, Some((_, rest))) => rest,
+                        _ => body,
+                    };
+                    return Ok(CapturedOutput::new(
+                        body.as_bytes(),
+                        exit_code,
+                        false,
+                        truncated,
+                    ));
+                }
+            }
+
+            if started.elapsed() >= timeout {
+                let (bytes, truncated) = {
+                    let scrollback = output.scrollback.lock();
+                    scrollback.retained_range(start, scrollback.total)
+                };
+                return Ok(CapturedOutput::new(&bytes, None, true, truncated));
+            }
+            thread::sleep(Duration::from_millis(25));
+        }
+    }
+
+    /// Forget a session and terminate its process group, escalating to
+    /// SIGKILL after `grace`.
+    fn kill(&self, session_id: &str, grace: Duration) -> Result<(), CommandError> {
+        let removed = self.sessions.lock().remove(session_id);
+        let Some(session) = removed else {
+            return Err(CommandError::session_not_found(session_id));
+        };
+        let (pid, output, tmux_client) = {
+            let mut session_lock = session.lock();
+            (
+                session_lock.child_id,
+                session_lock.output.clone(),
+                session_lock.tmux_client.take(),
+            )
+        };
+        // Lets a non-blocking reader exit even if the process group lingers.
+        output.closing.store(true, Ordering::Release);
+
+        match tmux_client {
+            Some(client) if client.detach() => {
+                // A detached client exits by itself; stop it only if it lingers.
+                thread::spawn(move || {
+                    thread::sleep(grace);
+                    if process::is_process_alive(pid) {
+                        terminate_pid(pid, grace);
+                    }
+                });
+                log::info!(
+                    "Detached tmux in PTY session: {} (PID: {})",
+                    session_id,
+                    pid
+                );
+            }
+            _ => {
+                terminate_pid(pid, grace);
+                log::info!("Killed PTY session: {} (PID: {})", session_id, pid);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for PtyManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How PTY output bytes are encoded in `pty-output` events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputEncoding {
+    /// Lossy UTF-8 text (default, compatible with older frontends).
+    Utf8,
+    /// Raw bytes as standard base64, preserving binary and split sequences.
+    Base64,
+}
+
+impl OutputEncoding {
+    fn parse(value: Option<&str>) -> Result<Self, CommandError> {
+        match value {
+            None | Some("utf8") | Some("utf-8") => Ok(Self::Utf8),
+            Some("base64") => Ok(Self::Base64),
+            Some(other) => Err(CommandError::InvalidInput(format!(
+                "Unknown output encoding: {}",
+                other
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf8",
+            Self::Base64 => "base64",
+        }
+    }
+}
+
+/// Incremental UTF-8 decoder that carries incomplete trailing sequences
+/// over to the next read, so characters split across a read boundary
+/// aren't replaced with U+FFFD.
+#[derive(Debug, Default)]
+pub(crate) struct Utf8Decoder {
+    carry: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub(crate) fn decode(&mut self, bytes: &[u8]) -> String {
+        let mut input = std::mem::take(&mut self.carry);
+        input.extend_from_slice(bytes);
+
+        let mut out = String::with_capacity(input.len());
+        let mut rest: &[u8] = &input;
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    out.push_str(valid);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    // `valid` is entirely valid UTF-8, so this never substitutes.
+                    out.push_str(&String::from_utf8_lossy(valid));
+                    match e.error_len() {
+                        Some(len) => {
+                            out.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        None => {
+                            // Incomplete sequence at the end; wait for more bytes.
+                            self.carry = after.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// Flush at end of input: a truncated trailing character becomes
+    /// U+FFFD rather than disappearing.
+    pub(crate) fn finish(&mut self) -> String {
+        if self.carry.is_empty() {
+            return String::new();
+        }
+        self.carry.clear();
+        char::REPLACEMENT_CHARACTER.to_string()
+    }
+}
+
+/// Payload of the `pty-output` event.
+#[derive(Debug, Clone, serde::Serialize)]
+struct PtyOutput {
+    session_id: String,
+    data: String,
+    encoding: &'static str,
+    /// Per-session event counter starting at 1 with no gaps, so the
+    /// frontend can detect lost or reordered events and repaint from
+    /// `read_pty_buffer`.
+    seq: u64,
+}
+
+/// Encodes PTY output for one session and emits `pty-output` events.
+struct OutputEmitter {
+    events: Arc<dyn SessionEvents>,
+    session_id: String,
+    encoding: OutputEncoding,
+    decoder: Utf8Decoder,
+    /// `seq` of the last emitted event.
+    seq: u64,
+}
+
+impl OutputEmitter {
+    fn emit(&mut self, bytes: &[u8]) {
+        let data = match self.encoding {
+            OutputEncoding::Utf8 => self.decoder.decode(bytes),
+            OutputEncoding::Base64 => BASE64.encode(bytes),
+        };
+        self.send(data);
+    }
+
+    /// Emit what the decoder still holds; called at EOF.
+    fn finish(&mut self) {
+        let data = self.decoder.finish();
+        self.send(data);
+    }
+
+    fn send(&mut self, data: String) {
+        if data.is_empty() {
+            return;
+        }
+        self.seq += 1;
+        self.events.send(SessionEvent::Output(PtyOutput {
+            session_id: self.session_id.clone(),
+            data,
+            encoding: self.encoding.as_str(),
+            seq: self.seq,
+        }));
+    }
+}
+
+/// Window over which output is coalesced once the rate threshold is hit
+/// (roughly one display frame).
+const COALESCE_WINDOW: Duration = Duration::from_millis(16);
+
+/// Destination for PTY output read by the reader thread.
+enum OutputSink {
+    /// Emit every read immediately.
+    Direct(OutputEmitter),
+    /// Hand reads to an emitter thread that batches them under load.
+    Coalesced {
+        tx: mpsc::Sender<Vec<u8>>,
+        handle: thread::JoinHandle<()>,
+    },
+}
+
+impl OutputSink {
+    /// `max_bytes_per_sec` of 0 disables coalescing.
+    fn new(emitter: OutputEmitter, max_bytes_per_sec: u64) -> Self {
+        if max_bytes_per_sec == 0 {
+            return Self::Direct(emitter);
+        }
+
+        let budget = (u128::from(max_bytes_per_sec) * COALESCE_WINDOW.as_millis() / 1000)
+            .clamp(1, usize::MAX as u128) as usize;
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let handle = thread::spawn(move || run_coalescing_emitter(emitter, rx, budget));
+        Self::Coalesced { tx, handle }
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Direct(emitter) => emitter.emit(bytes),
+            Self::Coalesced { tx, .. } => {
+                let _ = tx.send(bytes.to_vec());
+            }
+        }
+    }
+
+    /// Flush any buffered output; returns once it has all been emitted.
+    fn finish(self) {
+        match self {
+            Self::Direct(mut emitter) => emitter.finish(),
+            Self::Coalesced { tx, handle } => {
+                drop(tx);
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// Emit reads as they arrive while under `budget` bytes per window; past
+/// that, batch everything received in the window into a single emit.
+fn run_coalescing_emitter(mut emitter: OutputEmitter, rx: mpsc::Receiver<Vec<u8>>, budget: usize) {
+    let mut window_start = Instant::now();
+    let mut window_bytes = 0usize;
+    let mut pending: Vec<u8> = Vec::new();
+
+    loop {
+        let elapsed = window_start.elapsed();
+        if elapsed >= COALESCE_WINDOW {
+            window_start = Instant::now();
+            window_bytes = 0;
+            if !pending.is_empty() {
+                emitter.emit(&pending);
+                pending.clear();
+            }
+            continue;
+        }
+
+        match rx.recv_timeout(COALESCE_WINDOW - elapsed) {
+            Ok(chunk) => {
+                window_bytes += chunk.len();
+                pending.extend_from_slice(&chunk);
+                if window_bytes <= budget {
+                    emitter.emit(&pending);
+                    pending.clear();
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    if !pending.is_empty() {
+        emitter.emit(&pending);
+    }
+    emitter.finish();
+}
+
+/// Payload of the `pty-title` event.
+#[derive(Debug, Clone, serde::Serialize)]
+struct PtyTitle {
+    session_id: String,
+    title: String,
+}
+
+/// Payload of the `pty-clipboard-request` event.
+#[derive(Debug, Clone, serde::Serialize)]
+struct PtyClipboardRequest {
+    session_id: String,
+    data: String,
+}
+
+/// Payload of the `pty-command` event.
+#[derive(Debug, Clone, serde::Serialize)]
+struct PtyCommand {
+    session_id: String,
+    command_text: Option<String>,
+    exit_code: Option<i32>,
+}
+
+/// Payload of the `pty-exit` event.
+#[derive(Debug, Clone, serde::Serialize)]
+struct PtyExit {
+    session_id: String,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    /// Set when the backend ended the session, e.g. `"idle_timeout"`.
+    reason: Option<&'static str>,
+}
+
+/// Something a running session reports to the frontend.
+enum SessionEvent {
+    Output(PtyOutput),
+    Title(PtyTitle),
+    ClipboardRequest(PtyClipboardRequest),
+    /// OSC 52 write the session may make without asking.
+    ClipboardWrite(String),
+    Command(PtyCommand),
+    Exit(PtyExit),
+}
+
+/// Receives session events. The app emits them to the webview; tests
+/// collect them.
+trait SessionEvents: Send + Sync {
+    fn send(&self, event: SessionEvent);
+}
+
+impl SessionEvents for AppHandle {
+    fn send(&self, event: SessionEvent) {
+        let _ = match event {
+            SessionEvent::Output(payload) => self.emit("pty-output", payload),
+            SessionEvent::Title(payload) => self.emit("pty-title", payload),
+            SessionEvent::ClipboardRequest(payload) => self.emit("pty-clipboard-request", payload),
+            SessionEvent::Command(payload) => self.emit("pty-command", payload),
+            SessionEvent::Exit(payload) => self.emit("pty-exit", payload),
+            SessionEvent::ClipboardWrite(text) => {
+                if let Err(e) = self.state::<ClipboardState>().set_text(text) {
+                    log::warn!("OSC 52 clipboard write failed: {}", e);
+                }
+                Ok(())
+            }
+        };
+    }
+}
+
+/// How long the waiter thread lets the reader finish delivering output
+/// before emitting `pty-exit`.
+const READER_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn emit_pty_exit(
+    events: &dyn SessionEvents,
+    session_id: &str,
+    status: Option<&portable_pty::ExitStatus>,
+    reason: Option<&'static str>,
+) {
+    let (exit_code, signal) = match status {
+        Some(status) => match status.signal() {
+            Some(name) => (None, signal_from_description(name)),
+            None => (Some(status.exit_code() as i32), None),
+        },
+        None => (None, None),
+    };
+    events.send(SessionEvent::Exit(PtyExit {
+        session_id: session_id.to_string(),
+        exit_code,
+        signal,
+        reason,
+    }));
+}
+
+/// Recover a signal number from the `strsignal` description that
+/// portable-pty stores in `ExitStatus`.
+#[cfg(unix)]
+fn signal_from_description(description: &str) -> Option<i32> {
+    (1..32).find(|&signum| {
+        let ptr = unsafe { libc::strsignal(signum) };
+        if ptr.is_null() {
+            return false;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(ptr) };
+        name.to_string_lossy() == description
+    })
+}
+
+#[cfg(not(unix))]
+fn signal_from_description(_description: &str) -> Option<i32> {
+    None
+}
+
+/// SIGTERM the process group led by `pid`, then SIGKILL it from a
+/// background thread once `grace` has passed or the leader has exited,
+/// whichever comes first.
+#[cfg(unix)]
+fn terminate_pid(pid: u32, grace: Duration) {
+    if pid == 0 {
+        return;
+    }
+
+    // Negative PID targets the whole process group led by the shell.
+    let group = -(pid as libc::pid_t);
+    unsafe { libc::kill(group, libc::SIGTERM) };
+
+    let _ = thread::spawn(move || {
+        let started = Instant::now();
+        while started.elapsed() < grace && process::is_process_alive(pid) {
+            thread::sleep(Duration::from_millis(25));
+        }
+        // Also reaches children left behind by a shell that exited on SIGTERM.
+        unsafe { libc::kill(group, libc::SIGKILL) };
+    });
+}
+
+#[cfg(not(unix))]
+fn terminate_pid(_pid: u32, _grace: Duration) {}
+
+/// Everything needed to start a program under a new PTY session.
+struct SpawnRequest {
+    program: String,
+    args: Vec<String>,
+    rows: Option<u16>,
+    cols: Option<u16>,
+    cwd: Option<String>,
+    env_vars: Option<HashMap<String, String>>,
+    scrollback_bytes: Option<usize>,
+    encoding: OutputEncoding,
+    max_output_rate: Option<u64>,
+    clean_env: bool,
+    idle_timeout: Option<Duration>,
+    /// Start in the home directory if `cwd` is invalid instead of failing.
+    cwd_fallback: bool,
+    audited: bool,
+    normalize_input: bool,
+    /// Emit output in whole lines; see `spawn_shell`.
+    line_buffered: bool,
+}
+
+/// `TERM` and `COLORTERM` for every session, matching what xterm.js
+/// supports.
+const TERM: &str = "xterm-256color";
+const COLORTERM: &str = "truecolor";
+
+/// Variables copied from the app's environment when `clean_env` is set:
+/// enough to find programs (`PATH`), locate the user (`HOME`, `USER`) and
+/// pick a locale (`LANG`). `TERM` and `COLORTERM` are always set by us.
+const BASELINE_ENV: &[&str] = &["PATH", "HOME", "USER", "LANG"];
+
+/// Build a session's environment, each layer overriding the one before:
+/// the app's environment (or just `BASELINE_ENV` with `clean_env`), the
+/// `set_default_env` defaults, the per-spawn `env_vars`, then `TERM` and
+/// `COLORTERM`.
+fn apply_env(
+    cmd: &mut CommandBuilder,
+    clean_env: bool,
+    defaults: &HashMap<String, String>,
+    env_vars: HashMap<String, String>,
+) {
+    if clean_env {
+        cmd.env_clear();
+        for key in BASELINE_ENV {
+            if let Ok(value) = std::env::var(key) {
+                cmd.env(key, value);
+            }
+        }
+    }
+    for (key, value) in defaults {
+        cmd.env(key, value);
+    }
+    for (key, value) in env_vars {
+        cmd.env(key, value);
+    }
+    cmd.env("TERM", TERM);
+    cmd.env("COLORTERM", COLORTERM);
+}
+
+/// A session created by `spawn_shell`, so the frontend doesn't need
+/// follow-up calls to learn its initial state.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SpawnedSession {
+    pub session_id: String,
+    pub child_id: u32,
+    /// The resolved shell program.
+    pub shell: String,
+    /// The canonical starting directory.
+    pub cwd: String,
+    pub rows: u16,
+    pub cols: u16,
+}
+
+/// Spawn a new PTY shell session and describe it.
+///
+/// `scrollback_bytes` bounds the output retained for `read_pty_buffer`
+/// (default 256 KB). That memory is held for the lifetime of the session,
+/// so N sessions cost roughly N × `scrollback_bytes`; pass 0 to disable.
+///
+/// `shell` overrides `$SHELL` and must appear in the built-in allowlist or
+/// `/etc/shells`. `args` replaces the default `--login` argument.
+///
+/// `output_encoding` selects the `pty-output` payload format: `"utf8"`
+/// (default, lossy) or `"base64"` (raw bytes).
+///
+/// `max_output_rate` (bytes/sec, default 0 = unlimited) coalesces output
+/// into one `pty-output` event per ~16 ms once exceeded, so runaway
+/// commands don't flood the webview. No output is dropped.
+///
+/// A repeated call with the same `idempotency_key` within 5 seconds returns
+/// the session from the first call, if it's still running, instead of
+/// spawning another shell.
+///
+/// With `clean_env`, the shell doesn't inherit the app's environment (which
+/// may hold secrets); it gets only `PATH`, `HOME`, `USER`, `LANG`, `TERM`,
+/// `COLORTERM` and `env_vars`.
+///
+/// `env_vars` override the app-wide defaults from `set_default_env` on
+/// conflict. `TERM` and `COLORTERM` can't be overridden.
+///
+/// `idle_timeout_secs` kills the session once it has had no input or
+/// output for that long; the `pty-exit` event then carries the reason
+/// `"idle_timeout"`. 0 or `None` disables it.
+///
+/// `cwd` may start with `~` and must be an existing directory; the
+/// session's stored cwd is the canonical path. An invalid `cwd` fails the
+/// spawn unless `cwd_fallback` is set, in which case the shell starts in
+/// the home directory.
+///
+/// `no_audit` marks a scratch session whose commands are kept out of the
+/// audit log: `write_log` drops entries with its session ID (see
+/// `is_audited`). Output is still kept in scrollback.
+///
+/// `normalize_input` makes `write_to_pty` convert text to Unicode NFC, so
+/// programs see composed characters however the input method sent them.
+/// It's off by default for programs that expect input unchanged.
+///
+/// `line_buffered` emits `pty-output` on line boundaries instead of
+/// whenever output is read, for consumers that parse output by line. A
+/// trailing partial line is held until its newline arrives, more than
+/// 16 KB is pending, or output pauses (about 100 ms on Unix; elsewhere
+/// only the size limit applies). Scrollback and recordings aren't
+/// affected. Off by default, since holding output makes typing feel slower.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_shell(
+    app: AppHandle,
+    rows: Option<u16>,
+    cols: Option<u16>,
+    cwd: Option<String>,
+    env_vars: Option<HashMap<String, String>>,
+    scrollback_bytes: Option<usize>,
+    shell: Option<String>,
+    args: Option<Vec<String>>,
+    output_encoding: Option<String>,
+    max_output_rate: Option<u64>,
+    idempotency_key: Option<String>,
+    clean_env: Option<bool>,
+    idle_timeout_secs: Option<u64>,
+    cwd_fallback: Option<bool>,
+    no_audit: Option<bool>,
+    normalize_input: Option<bool>,
+    line_buffered: Option<bool>,
+) -> Result<SpawnedSession, CommandError> {
+    let encoding = OutputEncoding::parse(output_encoding.as_deref())?;
+    let shell = match shell {
+        Some(requested) => validate_shell(&requested)?,
+        None => select_shell(),
+    };
+
+    let manager = app.state::<PtyManager>();
+    let slot = idempotency_key.map(|key| manager.spawn_slot(&key));
+    let mut previous = slot.as_ref().map(|slot| slot.lock());
+    if let Some(Some((session_id, at))) = previous.as_deref() {
+        if at.elapsed() < IDEMPOTENCY_WINDOW {
+            if let Ok(spawned) = manager.spawned_session(session_id) {
+                log::info!("Reusing session {} for repeated spawn", session_id);
+                return Ok(spawned);
+            }
+        }
+    }
+
+    let session_id = manager.spawn(
+        Arc::new(app.clone()),
+        SpawnRequest {
+            program: shell,
+            args: args.unwrap_or_else(|| vec!["--login".to_string()]),
+            rows,
+            cols,
+            cwd,
+            env_vars,
+            scrollback_bytes,
+            encoding,
+            max_output_rate,
+            clean_env: clean_env.unwrap_or(false),
+            idle_timeout: idle_timeout_secs
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            cwd_fallback: cwd_fallback.unwrap_or(false),
+            audited: !no_audit.unwrap_or(false),
+            normalize_input: normalize_input.unwrap_or(false),
+            line_buffered: line_buffered.unwrap_or(false),
+        },
+    )?;
+
+    if let Some(previous) = previous.as_deref_mut() {
+        *previous = Some((session_id.clone(), Instant::now()));
+    }
+    manager.spawned_session(&session_id)
+}
+
+/// Run a single program directly under a new PTY, without a login shell.
+///
+/// `program` must be an absolute path or a name found in `PATH` (the
+/// `PATH` in `env_vars` if given). The session behaves like a shell session
+/// and is removed when the program exits. Returns the session ID.
+#[tauri::command]
+pub fn spawn_command(
+    app: AppHandle,
+    program: String,
+    args: Option<Vec<String>>,
+    rows: Option<u16>,
+    cols: Option<u16>,
+    cwd: Option<String>,
+    env_vars: Option<HashMap<String, String>>,
+) -> Result<String, CommandError> {
+    let search_path = env_vars
+        .as_ref()
+        .and_then(|vars| vars.get("PATH").cloned())
+        .or_else(|| std::env::var("PATH").ok())
+        .unwrap_or_default();
+    let program = resolve_program(&program, &search_path)?;
+
+    app.state::<PtyManager>().spawn(
+        Arc::new(app.clone()),
+        SpawnRequest {
+            program,
+            args: args.unwrap_or_default(),
+            rows,
+            cols,
+            cwd,
+            env_vars,
+            scrollback_bytes: None,
+            encoding: OutputEncoding::Utf8,
+            max_output_rate: None,
+            clean_env: false,
+            idle_timeout: None,
+            cwd_fallback: false,
+            audited: true,
+            normalize_input: false,
+            line_buffered: false,
+        },
+    )
+}
+
+/// A tmux client running in a session, attached by `spawn_tmux_attach`.
+struct TmuxClient {
+    tmux: String,
+    /// The session's terminal, which identifies the client to tmux.
+    tty: std::path::PathBuf,
+}
+
+/// How long `tmux detach-client` may take before the client is killed.
+const TMUX_DETACH_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl TmuxClient {
+    /// Ask tmux to detach this client, leaving the tmux session running.
+    fn detach(&self) -> bool {
+        let output = process::output_with_timeout(
+            std::process::Command::new(&self.tmux)
+                .arg("detach-client")
+                .arg("-t")
+                .arg(&self.tty),
+            TMUX_DETACH_TIMEOUT,
+        );
+        matches!(output, Ok(Some(output)) if output.status.success())
+    }
+}
+
+/// Where tmux is commonly installed, searched after `PATH` since apps
+/// launched from the Finder get a minimal `PATH`.
+const TMUX_DIRS: &str = "/opt/homebrew/bin:/usr/local/bin:/opt/local/bin";
+
+/// Longest tmux session name `spawn_tmux_attach` accepts.
+const MAX_TMUX_NAME: usize = 64;
+
+/// tmux session names are passed as an argument, not through a shell, but
+/// tmux itself gives `:`, `.`, `=` and a leading `-` special meaning, so
+/// only plain names are allowed.
+fn is_valid_tmux_session_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_TMUX_NAME
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Open a session attached to the tmux session `tmux_session_name`,
+/// creating it if it doesn't exist (`tmux new-session -A -s NAME`), so its
+/// programs and output survive app restarts. Returns the session ID.
+///
+/// Names may contain ASCII letters, digits, `-` and `_` (not leading `-`),
+/// up to 64 characters. `kill_pty` detaches the client instead of killing
+/// it, leaving the tmux session running. Fails with `not_supported` if
+/// `tmux` isn't installed.
+#[tauri::command]
+pub fn spawn_tmux_attach(
+    app: AppHandle,
+    tmux_session_name: String,
+    rows: Option<u16>,
+    cols: Option<u16>,
+) -> Result<String, CommandError> {
+    if !is_valid_tmux_session_name(&tmux_session_name) {
+        return Err(CommandError::InvalidInput(format!(
+            "Invalid tmux session name: {:?}",
+            tmux_session_name
+        )));
+    }
+    let search_path = format!(
+        "{}:{}",
+        std::env::var("PATH").unwrap_or_default(),
+        TMUX_DIRS
+    );
+    let tmux = resolve_program("tmux", &search_path)
+        .map_err(|_| CommandError::NotSupported("tmux is not installed".to_string()))?;
+
+    let manager = app.state::<PtyManager>();
+    let session_id = manager.spawn(
+        Arc::new(app.clone()),
+        SpawnRequest {
+            program: tmux.clone(),
+            args: ["new-session", "-A", "-s", &tmux_session_name]
+                .map(str::to_string)
+                .to_vec(),
+            rows,
+            cols,
+            cwd: None,
+            env_vars: None,
+            scrollback_bytes: None,
+            encoding: OutputEncoding::Utf8,
+            max_output_rate: None,
+            clean_env: false,
+            idle_timeout: None,
+            cwd_fallback: false,
+            audited: true,
+            normalize_input: false,
+            line_buffered: false,
+        },
+    )?;
+
+    if let Ok(session) = manager.get_session(&session_id) {
+        let mut session_lock = session.lock();
+        session_lock.tmux_client = session_lock
+            .master
+            .tty_name()
+            .map(|tty| TmuxClient { tmux, tty });
+    }
+    log::info!(
+        "Attached PTY session {} to tmux session {}",
+        session_id,
+        tmux_session_name
+    );
+    Ok(session_id)
+}
+
+/// Spawn a new shell in another session's current directory, e.g. to
+/// split a tab. Returns the new session ID.
+///
+/// The directory is resolved as for `get_cwd` when the call is made. If it
+/// has since been removed, the shell starts in the home directory.
+///
+/// The source's shell is reused if it's still an allowed shell. Its
+/// `env_vars` and `clean_env`, variables set with `set_session_env`, and
+/// its `no_audit`, `normalize_input` and `line_buffered` flags are carried
+/// over. `encoding`, `scrollback_bytes`, `max_output_rate` and
+/// `idle_timeout` are not: the sibling gets the defaults.
+#[tauri::command]
+pub fn spawn_sibling(
+    app: AppHandle,
+    session_id: String,
+    rows: Option<u16>,
+    cols: Option<u16>,
+) -> Result<String, CommandError> {
+    let manager = app.state::<PtyManager>();
+    let source = manager.get_session(&session_id).map_err(|_| {
+        CommandError::SessionNotFound(format!("Source session {} no longer exists", session_id))
+    })?;
+    let (shell, env_vars, clean_env, audited, normalize_input, line_buffered) = {
+        let source_lock = source.lock();
+        let line_buffered = source_lock.output.flow.lock().lines.is_some();
+        let mut env_vars = source_lock.spawn_env.clone();
+        env_vars.extend(source_lock.env_overrides.clone());
+        (
+            source_lock.shell.clone(),
+            env_vars,
+            source_lock.clean_env,
+            source_lock.audited,
+            source_lock.normalize_input,
+            line_buffered,
+        )
+    };
+    let cwd = session_cwd(&app, &session_id)?;
+
+    let sibling = manager.spawn(
+        Arc::new(app.clone()),
+        SpawnRequest {
+            program: validate_shell(&shell).unwrap_or_else(|_| select_shell()),
+            args: vec!["--login".to_string()],
+            rows,
+            cols,
+            cwd: Some(cwd),
+            env_vars: Some(env_vars),
+            scrollback_bytes: None,
+            encoding: OutputEncoding::Utf8,
+            max_output_rate: None,
+            clean_env,
+            idle_timeout: None,
+            cwd_fallback: true,
+            audited,
+            normalize_input,
+            line_buffered,
+        },
+    )?;
+    log::info!("Spawned session {} as a sibling of {}", sibling, session_id);
+    Ok(sibling)
+}
+
+/// Expand a leading `~` to the user's home directory.
+fn expand_tilde(path: &str) -> Result<std::path::PathBuf, CommandError> {
+    match path.strip_prefix('~') {
+        Some(stripped) => {
+            let home = dirs::home_dir().ok_or_else(|| {
+                CommandError::NotSupported("Cannot resolve home directory".to_string())
+            })?;
+            Ok(home.join(stripped.trim_start_matches('/')))
+        }
+        None => Ok(std::path::PathBuf::from(path)),
+    }
+}
+
+fn home_dir_string() -> String {
+    dirs::home_dir()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| "/".to_string())
+}
+
+/// Expand and canonicalize a requested working directory, checking that
+/// it is an existing directory. `None` means the home directory.
+///
+/// With `fallback_to_home`, an invalid directory is logged and replaced by
+/// the home directory instead of failing the spawn.
+fn resolve_working_dir(cwd: Option<&str>, fallback_to_home: bool) -> Result<String, CommandError> {
+    let Some(cwd) = cwd else {
+        return Ok(home_dir_string());
+    };
+    let resolved = expand_tilde(cwd).and_then(|path| match std::fs::canonicalize(&path) {
+        Ok(path) if path.is_dir() => Ok(path),
+        Ok(_) => Err(CommandError::InvalidInput(format!(
+            "Working directory is not a directory: {}",
+            cwd
+        ))),
+        Err(e) => Err(CommandError::io(
+            &format!("Invalid working directory {}", cwd),
+            e,
+        )),
+    });
+
+    match resolved {
+        Ok(path) => Ok(path.to_string_lossy().to_string()),
+        Err(e) if fallback_to_home => {
+            log::warn!("{}; starting in the home directory", e.message());
+            Ok(home_dir_string())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Resolve `program` to an absolute path of an executable file.
+fn resolve_program(program: &str, search_path: &str) -> Result<String, CommandError> {
+    if program.is_empty() {
+        return Err(CommandError::InvalidInput(
+            "Program must not be empty".to_string(),
+        ));
+    }
+
+    if program.contains('/') {
+        if !program.starts_with('/') {
+            return Err(CommandError::InvalidInput(format!(
+                "Program must be an absolute path or a name in PATH: {}",
+                program
+            )));
+        }
+        if !is_executable(std::path::Path::new(program)) {
+            return Err(CommandError::InvalidInput(format!(
+                "Program is not an executable file: {}",
+                program
+            )));
+        }
+        return Ok(program.to_string());
+    }
+
+    search_path
+        .split(':')
+        .filter(|dir| dir.starts_with('/'))
+        .map(|dir| std::path::Path::new(dir).join(program))
+        .find(|candidate| is_executable(candidate))
+        .map(|found| found.to_string_lossy().to_string())
+        .ok_or_else(|| {
+            CommandError::InvalidInput(format!("Program not found in PATH: {}", program))
+        })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &std::path::Path) -> bool {
+    path.is_file()
+}
+
+fn select_shell() -> String {
+    let fallback = "/bin/zsh".to_string();
+    let raw = std::env::var("SHELL").unwrap_or_else(|_| fallback.clone());
+    // Only allow known system shells to avoid executing an unexpected binary.
+    match raw.as_str() {
+        "/bin/zsh" | "/bin/bash" | "/bin/sh" => raw,
+        _ => fallback,
+    }
+}
+
+/// Shells that are always accepted, even if `/etc/shells` is missing.
+const BUILTIN_SHELLS: &[&str] = &["/bin/zsh", "/bin/bash", "/bin/sh"];
+
+/// Built-in shells plus every absolute path listed in `/etc/shells`.
+fn allowed_shells() -> Vec<String> {
+    let mut shells: Vec<String> = BUILTIN_SHELLS.iter().map(|s| s.to_string()).collect();
+
+    if let Ok(contents) = std::fs::read_to_string("/etc/shells") {
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || !line.starts_with('/') {
+                continue;
+            }
+            if !shells.iter().any(|s| s == line) {
+                shells.push(line.to_string());
+            }
+        }
+    }
+
+    shells
+}
+
+/// Validate an explicitly requested shell against the allowlist.
+fn validate_shell(shell: &str) -> Result<String, CommandError> {
+    if allowed_shells().iter().any(|s| s == shell) {
+        Ok(shell.to_string())
+    } else {
+        Err(CommandError::InvalidInput(format!(
+            "Shell {} is not an allowed login shell",
+            shell
+        )))
+    }
+}
+
+/// Get the shell `spawn_shell` uses when none is given: `$SHELL` if it's
+/// one of the built-in shells, otherwise `/bin/zsh`.
+#[tauri::command]
+pub fn get_default_shell() -> String {
+    select_shell()
+}
+
+/// A shell offered by `list_available_shells`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ShellInfo {
+    pub path: String,
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// List shells that `spawn_shell` accepts and that exist on this machine,
+/// marking the one `get_default_shell` returns.
+#[tauri::command]
+pub fn list_available_shells() -> Vec<ShellInfo> {
+    let default = select_shell();
+    allowed_shells()
+        .into_iter()
+        .filter(|path| is_executable(std::path::Path::new(path)))
+        .map(|path| ShellInfo {
+            name: path.rsplit('/').next().unwrap_or(&path).to_string(),
+            is_default: path == default,
+            path,
+        })
+        .collect()
+}
+
+/// Payload of the `pty-blocked` event.
+#[derive(Debug, Clone, serde::Serialize)]
+struct PtyBlocked {
+    session_id: String,
+    command: String,
+    reason: String,
+}
+
+/// Write data to a PTY session.
+///
+/// While a command policy is set (see `set_command_policy`), input is held
+/// until Enter and each line is checked before it reaches the shell.
+///
+/// Sessions spawned with `normalize_input` get `data` in Unicode NFC. Each
+/// call is normalized on its own, so a combining mark sent in a later call
+/// than its base character isn't composed. `data` is always valid UTF-8:
+/// lone surrogates in the JSON payload fail deserialization before this
+/// runs.
+#[tauri::command]
+pub fn write_to_pty(app: AppHandle, session_id: String, data: String) -> Result<(), CommandError> {
+    let manager = app.state::<PtyManager>();
+    let data = manager.normalized_input(&session_id, data)?;
+    let Some(policy) = app.state::<CommandPolicyState>().current() else {
+        return manager.write(&session_id, data.as_bytes());
+    };
+    let Some((command, reason)) = manager.write_checked(&session_id, data.as_bytes(), &policy)?
+    else {
+        return Ok(());
+    };
+
+    log::warn!(
+        "Blocked command in PTY session {}: {}",
+        session_id,
+        redact_secrets(&reason)
+    );
+    let _ = app.emit(
+        "pty-blocked",
+        PtyBlocked {
+            session_id,
+            command,
+            reason: reason.clone(),
+        },
+    );
+    Err(CommandError::PermissionDenied(reason))
+}
+
+/// Write raw bytes to a PTY session without any string round-trip.
+///
+/// Use this for precise control bytes (e.g. 0x03) or binary paste payloads.
+/// Not subject to the command policy or `normalize_input`.
+#[tauri::command]
+pub fn write_to_pty_bytes(
+    app: AppHandle,
+    session_id: String,
+    data: Vec<u8>,
+) -> Result<(), CommandError> {
+    app.state::<PtyManager>().write(&session_id, &data)
+}
+
+/// Whether a shell accepts POSIX `export KEY='value'` syntax.
+fn is_posix_shell(shell: &str) -> bool {
+    let name = shell.rsplit('/').next().unwrap_or(shell);
+    matches!(
+        name,
+        "sh" | "bash" | "zsh" | "dash" | "ksh" | "mksh" | "ash"
+    )
+}
+
+fn is_valid_env_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Single-quote a value for POSIX shells; nothing inside is interpreted.
+fn posix_single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Set an environment variable in a running session's shell.
+///
+/// A running process's environment can't be changed from outside, so this
+/// types `export KEY='VALUE'` into the PTY. It only affects commands run
+/// after the shell reads that line, and is refused for non-POSIX shells.
+/// Values can't contain control characters, including tabs and newlines.
+#[tauri::command]
+pub fn set_session_env(
+    app: AppHandle,
+    session_id: String,
+    key: String,
+    value: String,
+) -> Result<(), CommandError> {
+    if !is_valid_env_key(&key) {
+        return Err(CommandError::InvalidInput(format!(
+            "Invalid environment variable name: {}",
+            key
+        )));
+    }
+    // The shell's line editor would act on these before the quoting applies.
+    if value.chars().any(char::is_control) {
+        return Err(CommandError::InvalidInput(
+            "Environment variable values must not contain control characters".to_string(),
+        ));
+    }
+
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let shell = {
+        let session_lock = session.lock();
+        if session_lock.env_overrides.get(&key) == Some(&value) {
+            // Already exported with this value; don't retype it.
+            return Ok(());
+        }
+        session_lock.shell.clone()
+    };
+    if !is_posix_shell(&shell) {
+        return Err(CommandError::NotSupported(format!(
+            "Cannot set environment for non-POSIX shell {}",
+            shell
+        )));
+    }
+
+    let line = format!("export {}={}\n", key, posix_single_quote(&value));
+    app.state::<PtyManager>()
+        .write(&session_id, line.as_bytes())?;
+    session.lock().env_overrides.insert(key.clone(), value);
+
+    log::info!("Set {} in PTY session {}", key, session_id);
+    Ok(())
+}
+
+fn get_default_env_path() -> std::path::PathBuf {
+    config::config_path("default_env.json")
+}
+
+/// Defaults saved by `set_default_env`, or none if unset or unreadable.
+fn load_default_env() -> HashMap<String, String> {
+    let Ok(json) = std::fs::read_to_string(get_default_env_path()) else {
+        return HashMap::new();
+    };
+    serde_json::from_str(&json).unwrap_or_else(|e| {
+        log::warn!("Failed to parse default environment: {}", e);
+        HashMap::new()
+    })
+}
+
+/// Set environment variables given to every session spawned from now on,
+/// such as proxy settings, replacing any previous defaults. They're saved
+/// and apply after a restart; an empty map clears them.
+///
+/// A session's environment is built from, lowest precedence first: the
+/// app's environment, these defaults, the spawn's `env_vars`, and finally
+/// `TERM`/`COLORTERM`. Defaults apply with `clean_env` too. Running
+/// sessions are unaffected; see `set_session_env`.
+#[tauri::command]
+pub fn set_default_env(app: AppHandle, vars: HashMap<String, String>) -> Result<(), CommandError> {
+    if let Some(key) = vars.keys().find(|key| !is_valid_env_key(key)) {
+        return Err(CommandError::InvalidInput(format!(
+            "Invalid environment variable name: {}",
+            key
+        )));
+    }
+    if vars.values().any(|value| value.contains('\0')) {
+        return Err(CommandError::InvalidInput(
+            "Environment variable values must not contain NUL".to_string(),
+        ));
+    }
+
+    let path = get_default_env_path();
+    if vars.is_empty() {
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(CommandError::io("Failed to remove default environment", e)),
+        }
+    } else {
+        let json = serde_json::to_string_pretty(&vars)
+            .map_err(|e| CommandError::InvalidInput(e.to_string()))?;
+        // Values may hold credentials, e.g. in a proxy URL.
+        config::write_private(&path, json.as_bytes())
+            .map_err(|e| CommandError::io("Failed to save default environment", e))?;
+    }
+
+    log::info!("Set {} default environment variables", vars.len());
+    *app.state::<PtyManager>().default_env.lock() = vars;
+    Ok(())
+}
+
+/// Run `commands` in a session once its shell is ready, e.g. to activate a
+/// virtualenv or source a profile. Each is typed with a trailing newline,
+/// in order, when the shell first reaches its prompt: on its first OSC 133
+/// mark, or for shells without integration once the session is 2 seconds
+/// old and output has paused. Calls made before then add to the queue.
+///
+/// Commands are typed as given, so they're interpreted by the shell like
+/// typed input; each must be a single non-empty line with no control
+/// characters and be allowed by the command policy, which is checked when
+/// they're queued. Fails with `invalid_state` if the first prompt has already
+/// passed, unless `allow_late` is set, in which case they're typed at once.
+/// Commands run in an audited session are logged with source `system`.
+#[tauri::command]
+pub fn queue_startup_commands(
+    app: AppHandle,
+    session_id: String,
+    commands: Vec<String>,
+    allow_late: Option<bool>,
+) -> Result<(), CommandError> {
+    if let Some(policy) = app.state::<CommandPolicyState>().current() {
+        for command in &commands {
+            policy
+                .check(command)
+                .map_err(CommandError::PermissionDenied)?;
+        }
+    }
+    let manager = app.state::<PtyManager>();
+    let audited = manager.get_session(&session_id)?.lock().audited;
+    let logger = app.clone();
+    let sid = session_id.clone();
+    let on_run = move |command: &str| {
+        if !audited {
+            return;
+        }
+        if let Err(e) = logger
+            .state::<LoggerState>()
+            .log_system_command(command, &sid)
+        {
+            log::warn!("Failed to log startup command: {}", e);
+        }
+    };
+    manager.queue_startup_commands(
+        &session_id,
+        commands,
+        allow_late.unwrap_or(false),
+        Arc::new(on_run),
+    )
+}
+
+/// Upper bound on how long `get_env` waits for `env` output.
+const ENV_CAPTURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Output silence after which `get_env` assumes `env` has finished.
+const ENV_CAPTURE_QUIET: Duration = Duration::from_millis(150);
+
+/// Most output `get_env` will collect (256 KB).
+const ENV_CAPTURE_CAP: usize = 256 * 1024;
+
+/// Read a running session's environment, with secrets redacted.
+///
+/// A child's environment can't be inspected portably, so this types `env`
+/// into the PTY and parses the `KEY=VALUE` lines of the output burst that
+/// follows. Best-effort: it only works while an interactive POSIX shell is
+/// at its prompt, the command and its output show up in the terminal, and
+/// other output arriving at the same time may be mixed in. Multi-line
+/// values are truncated to their first line.
+#[tauri::command(async)]
+pub fn get_env(
+    app: AppHandle,
+    session_id: String,
+) -> Result<HashMap<String, String>, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    {
+        let mut capture = output.capture.lock();
+        if capture.is_some() {
+            return Err(CommandError::InvalidState(
+                "Already reading this session's environment".to_string(),
+            ));
+        }
+        *capture = Some(Vec::new());
+    }
+    if let Err(e) = app.state::<PtyManager>().write(&session_id, b"env\n") {
+        *output.capture.lock() = None;
+        return Err(e);
+    }
+
+    let started = Instant::now();
+    let mut last_len = 0;
+    let mut last_change = started;
+    while started.elapsed() < ENV_CAPTURE_TIMEOUT {
+        thread::sleep(Duration::from_millis(25));
+        let len = output.capture.lock().as_ref().map_or(0, Vec::len);
+        if len != last_len {
+            last_len = len;
+            last_change = Instant::now();
+        } else if len > 0 && last_change.elapsed() >= ENV_CAPTURE_QUIET {
+            break;
+        }
+    }
+
+    let captured = output.capture.lock().take().unwrap_or_default();
+    Ok(parse_env_output(&String::from_utf8_lossy(&captured)))
+}
+
+/// How long `run_and_capture` waits by default.
+const DEFAULT_CAPTURE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Result of `run_and_capture`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct CapturedOutput {
+    /// Output with escape sequences removed.
+    pub output: String,
+    pub exit_code: Option<i32>,
+    /// The command hadn't finished; `output` is what it printed so far.
+    pub timed_out: bool,
+    /// Output beyond the session's scrollback size was lost from the start.
+    pub truncated: bool,
+}
+
+impl CapturedOutput {
+    fn new(bytes: &[u8], exit_code: Option<i32>, timed_out: bool, truncated: bool) -> Self {
+        let text = osc::strip_escapes(&String::from_utf8_lossy(bytes));
+        Self {
+            output: text.trim_end().to_string(),
+            exit_code,
+            timed_out,
+            truncated,
+        }
+    }
+}
+
+/// Run a command in a session and return its complete output, for scripted
+/// and AI flows that need a result rather than a stream.
+///
+/// Without `session_id` a new login shell is spawned for the command and
+/// killed afterwards; the command is typed once it reaches its first
+/// prompt. The command must be a single line; it is typed into the shell
+/// and also shows in the terminal. While a command policy is set, the
+/// command must pass it, as for `write_to_pty`.
+///
+/// When the shell sends OSC 133 marks, the output between the command's
+/// `C` and `D` marks is returned with its exit code, and a session that's
+/// busy with another command is refused. Otherwise (POSIX shells only) a
+/// `printf` of a unique sentinel and `$?` is appended after `;`, so a
+/// command ending in `&` or a comment won't be captured correctly.
+///
+/// After `timeout_ms` (default 30 s) the output so far is returned with
+/// `timed_out` set; the command keeps running.
+#[tauri::command(async)]
+pub fn run_and_capture(
+    app: AppHandle,
+    session_id: Option<String>,
+    command: String,
+    timeout_ms: Option<u64>,
+) -> Result<CapturedOutput, CommandError> {
+    if let Some(policy) = app.state::<CommandPolicyState>().current() {
+        let checked = policy::typed_line(command.as_bytes()).and_then(|text| {
+            if policy::strip_continuation(&text).is_some() {
+                return Err("Command ends in a line continuation".to_string());
+            }
+            policy.check(text.trim())
+        });
+        if let Err(reason) = checked {
+            log::warn!(
+                "Blocked captured command in PTY session {:?}: {}",
+                session_id,
+                redact_secrets(&reason)
+            );
+            if let Some(session_id) = session_id {
+                let _ = app.emit(
+                    "pty-blocked",
+                    PtyBlocked {
+                        session_id,
+                        command,
+                        reason: reason.clone(),
+                    },
+                );
+            }
+            return Err(CommandError::PermissionDenied(reason));
+        }
+    }
+    let manager = app.state::<PtyManager>();
+    let timeout = timeout_ms.map_or(DEFAULT_CAPTURE_TIMEOUT, Duration::from_millis);
+    let Some(session_id) = session_id else {
+        let session_id = manager.spawn(
+            Arc::new(app.clone()),
+            SpawnRequest {
+                program: select_shell(),
+                args: vec!["--login".to_string()],
+                rows: None,
+                cols: None,
+                cwd: None,
+                env_vars: None,
+                scrollback_bytes: None,
+                encoding: OutputEncoding::Utf8,
+                max_output_rate: None,
+                clean_env: false,
+                idle_timeout: None,
+                cwd_fallback: false,
+                audited: true,
+                normalize_input: false,
+                line_buffered: false,
+            },
+        )?;
+        // Skip login banners and the first prompt.
+        if let Ok(session) = manager.get_session(&session_id) {
+            let output = session.lock().output.clone();
+            output.wait_for_first_prompt(Some(timeout));
+        }
+        let captured = manager.run_and_capture(&session_id, &command, timeout);
+        let _ = manager.kill(&session_id, DEFAULT_KILL_GRACE);
+        return captured;
+    };
+    manager.run_and_capture(&session_id, &command, timeout)
+}
+
+/// Collect `KEY=VALUE` lines from captured `env` output, ignoring the
+/// echoed command, prompts and anything else that isn't an assignment.
+fn parse_env_output(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .filter_map(|line| {
+            let line = line.trim_end_matches('\r');
+            let (key, _) = line.split_once('=')?;
+            if !is_valid_env_key(key) {
+                return None;
+            }
+            // Redact the whole line so key-based rules (e.g. API_KEY=) apply.
+            let redacted = redact_secrets(line);
+            let (_, value) = redacted.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Read the most recent output retained for a session.
+///
+/// Used by the frontend to repaint the terminal after a webview reload.
+#[tauri::command]
+pub fn read_pty_buffer(
+    app: AppHandle,
+    session_id: String,
+    max_bytes: Option<usize>,
+) -> Result<String, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+
+    let bytes = output
+        .scrollback
+        .lock()
+        .tail(max_bytes.unwrap_or(usize::MAX));
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// Get retained output as plain text, e.g. for copying: escape sequences
+/// such as colors and title changes are removed, along with control
+/// characters other than newline and tab.
+///
+/// `start_line` (inclusive) and `end_line` (exclusive) select lines as
+/// numbered by `search_scrollback`; they default to the whole buffer.
+#[tauri::command]
+pub fn get_clean_scrollback(
+    app: AppHandle,
+    session_id: String,
+    start_line: Option<usize>,
+    end_line: Option<usize>,
+) -> Result<String, CommandError> {
+    let start = start_line.unwrap_or(0);
+    let end = end_line.unwrap_or(usize::MAX);
+    if start > end {
+        return Err(CommandError::InvalidInput(format!(
+            "start_line {} is after end_line {}",
+            start, end
+        )));
+    }
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+
+    let bytes = output.scrollback.lock().tail(usize::MAX);
+    Ok(clean_lines(&String::from_utf8_lossy(&bytes), start, end))
+}
+
+fn clean_lines(text: &str, start: usize, end: usize) -> String {
+    let lines: Vec<&str> = text
+        .split('\n')
+        .skip(start)
+        .take(end.saturating_sub(start))
+        .collect();
+    osc::strip_escapes(&lines.join("\n"))
+}
+
+/// Maximum number of matches returned by `search_scrollback`.
+const MAX_SCROLLBACK_MATCHES: usize = 1000;
+
+/// A single `search_scrollback` hit.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ScrollbackMatch {
+    /// Zero-based line number within the retained output.
+    pub line: usize,
+    /// Byte offset of the match within `text`.
+    pub offset: usize,
+    /// The full line containing the match, without its line ending.
+    pub text: String,
+}
+
+/// Result of `search_scrollback`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ScrollbackSearch {
+    pub matches: Vec<ScrollbackMatch>,
+    /// Older output was evicted, so line 0 may be a partial line and
+    /// earlier matches are gone.
+    pub wrapped: bool,
+}
+
+/// Search a session's retained output for `query`.
+///
+/// Searches the same buffer `read_pty_buffer` returns, so line numbers
+/// refer to that text. Case-insensitive matching (the default) folds ASCII
+/// letters only, keeping offsets exact. Raw output is searched, so a match
+/// interrupted by an escape sequence isn't found. Returns at most
+/// `MAX_SCROLLBACK_MATCHES` matches, oldest first.
+#[tauri::command]
+pub fn search_scrollback(
+    app: AppHandle,
+    session_id: String,
+    query: String,
+    case_sensitive: Option<bool>,
+) -> Result<ScrollbackSearch, CommandError> {
+    if query.is_empty() {
+        return Err(CommandError::InvalidInput(
+            "Query must not be empty".to_string(),
+        ));
+    }
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+
+    let (bytes, wrapped) = {
+        let scrollback = output.scrollback.lock();
+        (scrollback.tail(usize::MAX), scrollback.wrapped)
+    };
+    let text = String::from_utf8_lossy(&bytes);
+
+    Ok(ScrollbackSearch {
+        matches: find_line_matches(&text, &query, case_sensitive.unwrap_or(false)),
+        wrapped,
+    })
+}
+
+fn find_line_matches(text: &str, query: &str, case_sensitive: bool) -> Vec<ScrollbackMatch> {
+    let needle = if case_sensitive {
+        query.to_string()
+    } else {
+        query.to_ascii_lowercase()
+    };
+
+    let mut matches = Vec::new();
+    for (line_no, line) in text.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let haystack = if case_sensitive {
+            std::borrow::Cow::Borrowed(line)
+        } else {
+            std::borrow::Cow::Owned(line.to_ascii_lowercase())
+        };
+        for (offset, _) in haystack.match_indices(&needle) {
+            matches.push(ScrollbackMatch {
+                line: line_no,
+                offset,
+                text: line.to_string(),
+            });
+            if matches.len() >= MAX_SCROLLBACK_MATCHES {
+                return matches;
+            }
+        }
+    }
+    matches
+}
+
+/// Get a session's shell process and its descendants, e.g. to spot a
+/// background `npm run dev` that's still running. Returns just the shell
+/// when it has no children.
+///
+/// Memory is resident set size. CPU usage is recent on macOS and a
+/// lifetime average on Linux.
+#[tauri::command]
+pub fn get_process_tree(app: AppHandle, session_id: String) -> Result<ProcessNode, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let pid = session.lock().child_id;
+
+    let rows = process::process_table().map_err(|e| match e.kind() {
+        std::io::ErrorKind::Unsupported => CommandError::NotSupported(e.to_string()),
+        _ => CommandError::io("Failed to list processes", e),
+    })?;
+    process::build_tree(&rows, pid).ok_or_else(|| {
+        CommandError::InvalidState(format!("Session process {} is no longer running", pid))
+    })
+}
+
+/// Get the latest title a session set via OSC 0/2, if any.
+#[tauri::command]
+pub fn get_session_title(
+    app: AppHandle,
+    session_id: String,
+) -> Result<Option<String>, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    let title = output.title.lock().clone();
+    Ok(title)
+}
+
+/// What a session's terminal advertises, from `get_terminal_capabilities`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TerminalCapabilities {
+    /// `TERM` set for the session.
+    pub term: &'static str,
+    /// `COLORTERM` set for the session.
+    pub colorterm: &'static str,
+    /// 24-bit color escapes are rendered.
+    pub truecolor: bool,
+    /// Palette size for indexed colors.
+    pub colors: u32,
+    /// How OSC 52 clipboard writes are handled, per `set_osc52_policy`.
+    pub osc52: Osc52Policy,
+    /// The shell has reported its working directory via OSC 7.
+    pub cwd_reporting: bool,
+    /// The shell has marked prompts and commands via OSC 133.
+    pub shell_integration: bool,
+}
+
+/// Get what a session's terminal advertises, so the UI can configure
+/// xterm.js addons to match.
+///
+/// The static part is the `TERM`/`COLORTERM` environment every session is
+/// started with. DA1 and XTGETTCAP queries are answered by xterm.js, not
+/// the backend, so capabilities negotiated that way aren't reported; the
+/// OSC 7 and OSC 133 flags reflect what the shell has sent so far.
+#[tauri::command]
+pub fn get_terminal_capabilities(
+    app: AppHandle,
+    session_id: String,
+) -> Result<TerminalCapabilities, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    let osc52 = *output.osc52_policy.lock();
+    let cwd_reporting = output.reported_cwd.lock().is_some();
+    let shell_integration = !output.command_marks.lock().is_empty();
+    Ok(TerminalCapabilities {
+        term: TERM,
+        colorterm: COLORTERM,
+        truecolor: true,
+        colors: 256,
+        osc52,
+        cwd_reporting,
+        shell_integration,
+    })
+}
+
+/// Get the prompt, command and output regions of a session's recent
+/// commands (up to 1000), oldest first.
+///
+/// Regions come from OSC 133 marks, so the list stays empty unless the
+/// shell has the integration hook from `docs/architecture.md`. Each
+/// finished command is also announced with a `pty-command` event
+/// `{ session_id, command_text, exit_code }`.
+#[tauri::command]
+pub fn get_command_marks(
+    app: AppHandle,
+    session_id: String,
+) -> Result<Vec<CommandMark>, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    let marks = output.command_marks.lock().iter().cloned().collect();
+    Ok(marks)
+}
+
+/// Whether a session's shell is waiting for a command, from `is_at_prompt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct PromptState {
+    pub at_prompt: bool,
+    /// Leader of the terminal's foreground process group, where the
+    /// platform reports it.
+    pub foreground_pid: Option<u32>,
+}
+
+/// Check whether a session's shell is at its prompt rather than running a
+/// command, so automation doesn't type into another program.
+///
+/// Uses the latest OSC 133 marks when the shell sends them: the shell is at
+/// its prompt until a command starts executing and again once it finishes.
+/// Otherwise, on Unix, the shell is at its prompt when it leads the
+/// terminal's foreground process group; elsewhere `at_prompt` is false.
+#[tauri::command]
+pub fn is_at_prompt(app: AppHandle, session_id: String) -> Result<PromptState, CommandError> {
+    app.state::<PtyManager>().prompt_state(&session_id)
+}
+
+/// Whether a session's commands are written to the audit log, i.e. it
+/// wasn't spawned with `no_audit`. `write_log` checks this itself.
+#[tauri::command]
+pub fn is_audited(app: AppHandle, session_id: String) -> Result<bool, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let audited = session.lock().audited;
+    Ok(audited)
+}
+
+/// Get up to `n` of a session's most recent command exit codes, newest
+/// first (at most 32 are kept).
+///
+/// Codes come from OSC 133 `D;<status>` marks, so the list stays empty
+/// unless the shell has the integration hook from `docs/architecture.md`.
+#[tauri::command]
+pub fn get_recent_exit_codes(
+    app: AppHandle,
+    session_id: String,
+    n: usize,
+) -> Result<Vec<i32>, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    let codes = output
+        .exit_codes
+        .lock()
+        .iter()
+        .rev()
+        .take(n)
+        .copied()
+        .collect();
+    Ok(codes)
+}
+
+/// Set how a session handles OSC 52 clipboard writes from programs like
+/// tmux and vim: `"deny"` ignores them, `"prompt"` (the default) emits a
+/// `pty-clipboard-request` event `{ session_id, data }` for the frontend to
+/// confirm, and `"allow"` writes to the clipboard directly. Clipboard
+/// reads (OSC 52 queries) are never answered.
+#[tauri::command]
+pub fn set_osc52_policy(
+    app: AppHandle,
+    session_id: String,
+    policy: String,
+) -> Result<(), CommandError> {
+    let policy = Osc52Policy::parse(&policy)?;
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    *output.osc52_policy.lock() = policy;
+    log::info!("Set OSC 52 policy for {} to {:?}", session_id, policy);
+    Ok(())
+}
+
+/// Stop emitting `pty-output` for a session without stopping the process.
+///
+/// The reader keeps draining the PTY into scrollback and a hold buffer, so
+/// the child isn't blocked by brief pauses. Once 1 MB is held the reader
+/// stops reading and the child blocks on its next write until
+/// `resume_output` is called or it exits. Returns `false` if already paused.
+#[tauri::command]
+pub fn pause_output(app: AppHandle, session_id: String) -> Result<bool, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    Ok(!output.paused.swap(true, Ordering::AcqRel))
+}
+
+/// Resume `pty-output` for a paused session, emitting held output first.
+/// Returns `false` if the session wasn't paused.
+#[tauri::command]
+pub fn resume_output(app: AppHandle, session_id: String) -> Result<bool, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    Ok(output.resume())
+}
+
+/// Detach a session from the frontend, e.g. for a background tab, or
+/// reattach it.
+///
+/// While detached no `pty-output` is emitted, but the reader keeps
+/// draining the PTY so the child never blocks, and the latest output is
+/// kept, up to the session's scrollback size. Reattaching emits that as a
+/// single `pty-output` before live output resumes. Unlike `pause_output`,
+/// older output is dropped rather than held. Returns `false` if the session
+/// was already in the requested state.
+#[tauri::command]
+pub fn set_session_attached(
+    app: AppHandle,
+    session_id: String,
+    attached: bool,
+) -> Result<bool, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    if attached {
+        return Ok(output.attach());
+    }
+    let capacity = output.scrollback.lock().capacity;
+    Ok(output.detach(capacity))
+}
+
+/// Start recording a session's output to an asciinema v2 `.cast` file.
+///
+/// The header uses the session's current size; later resizes are recorded
+/// as `"r"` events. Recording stops on `stop_recording` or when the session
+/// exits. `path` must not exist yet.
+#[tauri::command]
+pub fn start_recording(
+    app: AppHandle,
+    session_id: String,
+    path: String,
+) -> Result<(), CommandError> {
+    let path = std::path::PathBuf::from(path);
+    if !path.is_absolute() {
+        return Err(CommandError::InvalidInput(
+            "Recording path must be absolute".to_string(),
+        ));
+    }
+
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let session_lock = session.lock();
+    let size = session_lock.size;
+
+    let mut recorder = session_lock.output.recorder.lock();
+    if recorder.is_some() {
+        return Err(CommandError::InvalidState(format!(
+            "Session {} is already being recorded",
+            session_id
+        )));
+    }
+    *recorder = Some(
+        CastRecorder::create(&path, size.cols, size.rows, &session_lock.shell)
+            .map_err(CommandError::Io)?,
+    );
+
+    log::info!(
+        "Started recording PTY session {} to {}",
+        session_id,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Stop an active recording for a session.
+#[tauri::command]
+pub fn stop_recording(app: AppHandle, session_id: String) -> Result<(), CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let output = session.lock().output.clone();
+    if !output.stop_recording() {
+        return Err(CommandError::InvalidState(format!(
+            "Session {} is not being recorded",
+            session_id
+        )));
+    }
+
+    log::info!("Stopped recording PTY session {}", session_id);
+    Ok(())
+}
+
+/// How often a replay waiting for its next event checks for `stop_replay`.
+const REPLAY_POLL: Duration = Duration::from_millis(50);
+
+/// Play back an asciinema v2 `.cast` file as `pty-output` events for a
+/// read-only terminal, without spawning anything. Returns the synthetic
+/// session ID the events carry.
+///
+/// Output events are emitted with their recorded timing divided by `speed`
+/// (e.g. 2.0 plays twice as fast); resize and input events are skipped.
+/// Playback ends with a `pty-exit` event whose `reason` is
+/// `"replay_finished"`, or `"replay_stopped"` after `stop_replay`.
+#[tauri::command]
+pub fn replay_session(
+    app: AppHandle,
+    cast_path: String,
+    speed: f64,
+) -> Result<String, CommandError> {
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(CommandError::InvalidInput(format!(
+            "Replay speed must be positive, got {}",
+            speed
+        )));
+    }
+    let path = expand_tilde(&cast_path)?;
+    let cast = recording::read_cast(&path).map_err(CommandError::InvalidInput)?;
+    let replay_id = app
+        .state::<PtyManager>()
+        .replay(Arc::new(app.clone()), cast, speed);
+    log::info!(
+        "Replaying {} as {} at {}x",
+        path.display(),
+        replay_id,
+        speed
+    );
+    Ok(replay_id)
+}
+
+/// Cancel a `replay_session` playback. Its `pty-exit` event follows.
+#[tauri::command]
+pub fn stop_replay(app: AppHandle, replay_id: String) -> Result<(), CommandError> {
+    app.state::<PtyManager>().stop_replay(&replay_id)
+}
+
+/// Quiet period `resize_pty` waits for before resizing the PTY, so a
+/// window drag doesn't make the program redraw at every intermediate size.
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Apply a session's pending resize once `RESIZE_DEBOUNCE` has passed since
+/// it was last requested.
+fn apply_debounced_resize(session: Arc<Mutex<PtySession>>) {
+    loop {
+        let wait = {
+            let mut session_lock = session.lock();
+            let Some((size, requested)) = session_lock.pending_resize else {
+                // Superseded by an immediate resize.
+                return;
+            };
+            let wait = RESIZE_DEBOUNCE.saturating_sub(requested.elapsed());
+            if wait.is_zero() {
+                if let Err(e) = resize_session(&mut session_lock, size.rows, size.cols) {
+                    log::warn!("Debounced PTY resize failed: {}", e);
+                }
+                return;
+            }
+            wait
+        };
+        thread::sleep(wait);
+    }
+}
+
+/// Without OSC 133 marks, how old a session must be before it's assumed to
+/// be at its first prompt, and how long its output must have been quiet.
+const STARTUP_FALLBACK_DELAY: Duration = Duration::from_secs(2);
+const STARTUP_QUIET: Duration = Duration::from_millis(200);
+
+/// How often a session is checked for its first prompt.
+const STARTUP_POLL: Duration = Duration::from_millis(25);
+
+/// Wait for a session's first prompt, then type its queued startup
+/// commands in order. Gives up if the session closes first.
+fn run_startup_commands(
+    session: Arc<Mutex<PtySession>>,
+    session_id: &str,
+    on_run: &(dyn Fn(&str) + Send + Sync),
+) {
+    let output = session.lock().output.clone();
+    if !output.wait_for_first_prompt(None) {
+        return;
+    }
+
+    let mut session_lock = session.lock();
+    for command in session_lock.startup_commands.take().unwrap_or_default() {
+        let line = format!("{}\n", command);
+        let written = session_lock
+            .writer
+            .write_all(line.as_bytes())
+            .and_then(|()| session_lock.writer.flush());
+        if let Err(e) = written {
+            log::warn!("Failed to run startup command in {}: {}", session_id, e);
+            return;
+        }
+        session_lock.output.touch();
+        on_run(&command);
+    }
+}
+
+/// Resize a session's PTY and record the resize if recording. Discards any
+/// pending debounced resize.
+fn resize_session(session: &mut PtySession, rows: u16, cols: u16) -> Result<(), CommandError> {
+    session.pending_resize = None;
+    let size = PtySize {
+        rows,
+        cols,
+        pixel_width: 0,
+        pixel_height: 0,
+    };
+    session.master.resize(size)?;
+    session.size = size;
+
+    let mut recorder = session.output.recorder.lock();
+    if let Some(active) = recorder.as_mut() {
+        if let Err(e) = active.resize(cols, rows) {
+            log::warn!("Stopping recording after write failure: {}", e);
+            *recorder = None;
+        }
+    }
+
+    Ok(())
+}
+
+/// Resize a PTY session. Calls are debounced: the PTY is resized to the
+/// latest requested size once no call has arrived for 50 ms, and
+/// `get_pty_size` reports the old size until then.
+#[tauri::command]
+pub fn resize_pty(
+    app: AppHandle,
+    session_id: String,
+    rows: u16,
+    cols: u16,
+) -> Result<(), CommandError> {
+    app.state::<PtyManager>()
+        .resize_debounced(&session_id, rows, cols)
+}
+
+/// Rows and columns of a session's PTY.
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct PtyDimensions {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+/// Get the size last applied to a session's PTY, so the frontend can
+/// detect drift from its own terminal dimensions.
+#[tauri::command]
+pub fn get_pty_size(app: AppHandle, session_id: String) -> Result<PtyDimensions, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let size = session.lock().size;
+    Ok(PtyDimensions {
+        rows: size.rows,
+        cols: size.cols,
+    })
+}
+
+/// Resize every open session, e.g. after a font size change.
+///
+/// Returns failures keyed by session ID; an empty map means every session
+/// was resized. Sessions that exit mid-way are skipped rather than reported.
+#[tauri::command]
+pub fn resize_all_sessions(
+    app: AppHandle,
+    rows: u16,
+    cols: u16,
+) -> Result<HashMap<String, CommandError>, CommandError> {
+    let manager = app.state::<PtyManager>();
+    let sessions: Vec<(String, Arc<Mutex<PtySession>>)> = manager
+        .sessions
+        .lock()
+        .iter()
+        .map(|(id, session)| (id.clone(), session.clone()))
+        .collect();
+
+    let mut errors = HashMap::new();
+    for (session_id, session) in sessions {
+        let result = resize_session(&mut session.lock(), rows, cols);
+        // Check membership after releasing the session lock; kill_pty takes
+        // the manager lock first.
+        if let Err(e) = result {
+            if manager.sessions.lock().contains_key(&session_id) {
+                errors.insert(session_id, e);
+            }
+        }
+    }
+
+    Ok(errors)
+}
+
+/// How long to wait after SIGTERM before sending SIGKILL, unless `kill_pty`
+/// is given `grace_ms`.
+const DEFAULT_KILL_GRACE: Duration = Duration::from_millis(750);
+
+/// Kill a PTY session.
+///
+/// SIGTERM goes to the shell's whole process group, so programs running in
+/// it are signalled too. Anything still running after `grace_ms` (default
+/// 750) is sent SIGKILL. Returns without waiting for the escalation; use a
+/// longer grace period for programs that need time to save or flush.
+///
+/// A `spawn_tmux_attach` session is detached instead, leaving the tmux
+/// session running; its client is only signalled if it hasn't exited after
+/// the grace period.
+#[tauri::command]
+pub fn kill_pty(
+    app: AppHandle,
+    session_id: String,
+    grace_ms: Option<u64>,
+) -> Result<(), CommandError> {
+    let grace = grace_ms.map_or(DEFAULT_KILL_GRACE, Duration::from_millis);
+    app.state::<PtyManager>().kill(&session_id, grace)
+}
+
+/// Terminate every session's process group and forget all sessions.
+///
+/// Unlike `kill_pty`, this blocks through the SIGTERM → SIGKILL escalation
+/// so it can run at app exit without leaving processes behind. Returns the
+/// number of sessions killed.
+#[tauri::command(async)]
+pub fn kill_all_sessions(app: AppHandle) -> usize {
+    let pids: Vec<u32> = app
+        .state::<PtyManager>()
+        .sessions
+        .lock()
+        .drain()
+        .map(|(_, session)| session.lock().child_id)
+        .collect();
+
+    terminate_process_groups(&pids);
+    if !pids.is_empty() {
+        log::info!("Killed {} PTY session(s)", pids.len());
+    }
+    pids.len()
+}
+
+#[cfg(unix)]
+fn terminate_process_groups(pids: &[u32]) {
+    let groups: Vec<libc::pid_t> = pids
+        .iter()
+        .filter(|&&pid| pid != 0)
+        .map(|&pid| -(pid as libc::pid_t))
+        .collect();
+    if groups.is_empty() {
+        return;
+    }
+
+    for &group in &groups {
+        unsafe { libc::kill(group, libc::SIGTERM) };
+    }
+    let deadline = Instant::now() + DEFAULT_KILL_GRACE;
+    while Instant::now() < deadline && pids.iter().any(|&pid| process::is_process_alive(pid)) {
+        thread::sleep(Duration::from_millis(25));
+    }
+    // Also reaches children left behind by a shell that exited on SIGTERM.
+    for &group in &groups {
+        unsafe { libc::kill(group, libc::SIGKILL) };
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate_process_groups(_pids: &[u32]) {}
+
+/// Map a signal name ("INT", "SIGINT", ...) to its platform signal number.
+#[cfg(unix)]
+fn signal_from_name(name: &str) -> Option<i32> {
+    let upper = name.trim().to_ascii_uppercase();
+    let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+    match bare {
+        "INT" => Some(libc::SIGINT),
+        "HUP" => Some(libc::SIGHUP),
+        "QUIT" => Some(libc::SIGQUIT),
+        "TERM" => Some(libc::SIGTERM),
+        "KILL" => Some(libc::SIGKILL),
+        "TSTP" => Some(libc::SIGTSTP),
+        "CONT" => Some(libc::SIGCONT),
+        "WINCH" => Some(libc::SIGWINCH),
+        "USR1" => Some(libc::SIGUSR1),
+        "USR2" => Some(libc::SIGUSR2),
+        _ => None,
+    }
+}
+
+/// Send a signal to the terminal's foreground job without tearing down the
+/// session, the way the line discipline delivers ^C or ^Z.
+///
+/// The target is the foreground process group of the PTY (`tcgetpgrp` on the
+/// master), falling back to the shell's group when that can't be read.
+/// Accepts names like "INT", "HUP", "TSTP", "CONT" and "QUIT" (with or
+/// without the "SIG" prefix). Use `kill_pty` for full teardown.
+#[tauri::command]
+pub fn signal_pty(app: AppHandle, session_id: String, signal: String) -> Result<(), CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let (pid, foreground) = {
+        let session_lock = session.lock();
+        (session_lock.child_id, session_lock.master.foreground_pid())
+    };
+
+    #[cfg(unix)]
+    {
+        let signum = signal_from_name(&signal)
+            .ok_or_else(|| CommandError::InvalidInput(format!("Unknown signal: {}", signal)))?;
+        if pid == 0 {
+            return Err(CommandError::InvalidState(format!(
+                "Session {} has no child process",
+                session_id
+            )));
+        }
+
+        // Negative PID targets the whole group: the running job, or the
+        // shell itself when it is sitting at its prompt.
+        let pgid = foreground.filter(|&pgid| pgid != 0).unwrap_or(pid);
+        let rc = unsafe { libc::kill(-(pgid as libc::pid_t), signum) };
+        if rc != 0 {
+            return Err(CommandError::io(
+                &format!(
+                    "Failed to send SIG{} to session {}",
+                    signal.trim().to_ascii_uppercase().trim_start_matches("SIG"),
+                    session_id
+                ),
+                std::io::Error::last_os_error(),
+            ));
+        }
+
+        log::info!(
+            "Sent signal {} to PTY session: {} (process group: {})",
+            signum,
+            session_id,
+            pgid
+        );
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (pid, foreground, signal);
+        Err(CommandError::NotSupported(
+            "signal_pty is only supported on Unix".to_string(),
+        ))
+    }
+}
+
+/// Summary of an active PTY session, as returned by `list_sessions`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SessionInfo {
+    pub session_id: String,
+    pub child_id: u32,
+    pub cwd: String,
+    pub live_cwd: Option<String>,
+    pub alive: bool,
+    /// False for `no_audit` scratch sessions.
+    pub audited: bool,
+}
+
+/// Liveness of a session's child process and reader thread.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SessionHealth {
+    pub session_id: String,
+    pub alive: bool,
+    pub reader_running: bool,
+}
+
+fn session_health_of(session_id: String, session: &Mutex<PtySession>) -> SessionHealth {
+    let (pid, output) = {
+        let session = session.lock();
+        (session.child_id, session.output.clone())
+    };
+    SessionHealth {
+        session_id,
+        alive: process::is_process_alive(pid),
+        reader_running: output.reader_running.load(Ordering::Acquire),
+    }
+}
+
+/// Check whether a session's child process still exists. Unlike
+/// `session_health`, this ignores the reader thread.
+#[tauri::command]
+pub fn session_child_alive(app: AppHandle, session_id: String) -> Result<bool, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    let pid = session.lock().child_id;
+    Ok(process::is_process_alive(pid))
+}
+
+/// Check that a session's child is still alive and its output is still
+/// being read. Either being `false` means the session is effectively dead.
+#[tauri::command]
+pub fn session_health(app: AppHandle, session_id: String) -> Result<SessionHealth, CommandError> {
+    let session = app.state::<PtyManager>().get_session(&session_id)?;
+    Ok(session_health_of(session_id, &session))
+}
+
+/// How often `pty-heartbeat` events are emitted.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Emit a `pty-heartbeat` event with each session's `SessionHealth` every
+/// `HEARTBEAT_INTERVAL`, so the UI notices sessions that die silently.
+pub fn start_heartbeat(app: AppHandle) {
+    let _ = thread::spawn(move || loop {
+        thread::sleep(HEARTBEAT_INTERVAL);
+        let sessions: Vec<(String, Arc<Mutex<PtySession>>)> = app
+            .state::<PtyManager>()
+            .sessions
+            .lock()
+            .iter()
+            .map(|(id, session)| (id.clone(), session.clone()))
+            .collect();
+        for (session_id, session) in sessions {
+            let _ = app.emit("pty-heartbeat", session_health_of(session_id, &session));
+        }
+    });
+}
+
+/// How often the idle reaper checks sessions against their `idle_timeout`.
+const IDLE_REAP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Kill sessions that have been idle longer than their `idle_timeout`,
+/// checking every `IDLE_REAP_INTERVAL` for the app's lifetime.
+pub fn start_idle_reaper(app: AppHandle) {
+    let _ = thread::spawn(move || loop {
+        thread::sleep(IDLE_REAP_INTERVAL);
+        let sessions: Vec<(String, Arc<Mutex<PtySession>>)> = app
+            .state::<PtyManager>()
+            .sessions
+            .lock()
+            .iter()
+            .map(|(id, session)| (id.clone(), session.clone()))
+            .collect();
+        for (session_id, session) in sessions {
+            let (idle_timeout, output) = {
+                let session_lock = session.lock();
+                (session_lock.idle_timeout, session_lock.output.clone())
+            };
+            let Some(idle_timeout) = idle_timeout else {
+                continue;
+            };
+            if output.last_activity.lock().elapsed() < idle_timeout {
+                continue;
+            }
+
+            *output.exit_reason.lock() = Some("idle_timeout");
+            log::info!(
+                "Session {} idle for {:?}; killing",
+                session_id,
+                idle_timeout
+            );
+            let _ = app
+                .state::<PtyManager>()
+                .kill(&session_id, DEFAULT_KILL_GRACE);
+        }
+    });
+}
+
+/// List all active PTY sessions so the UI can reconcile its tabs.
+#[tauri::command]
+pub fn list_sessions(app: AppHandle) -> Result<Vec<SessionInfo>, CommandError> {
+    let state = app.state::<PtyManager>();
+    let snapshot: Vec<(String, u32, String, bool)> = {
+        let sessions = state.sessions.lock();
+        sessions
+            .iter()
+            .map(|(id, s)| {
+                let s = s.lock();
+                (id.clone(), s.child_id, s.cwd.clone(), s.audited)
+            })
+            .collect()
+    };
+
+    // Query live process state outside the sessions lock (lsof can be slow).
+    let mut out: Vec<SessionInfo> = snapshot
+        .into_iter()
+        .map(|(session_id, child_id, cwd, audited)| {
+            let alive = process::is_process_alive(child_id);
+            let live_cwd = if alive {
+                get_process_cwd(child_id)
+            } else {
+                None
+            };
+            SessionInfo {
+                session_id,
+                child_id,
+                cwd,
+                live_cwd,
+                alive,
+                audited,
+            }
+        })
+        .collect();
+
+    out.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+    Ok(out)
+}
+
+/// Get the current working directory of a session.
+///
+/// Returns the directory last reported by the shell via OSC 7 when it's
+/// the same directory as the child process's actual CWD. The report is
+/// checked against `/proc` on Linux on every call; elsewhere `lsof` runs
+/// only when the report changes. Without a report, or if it doesn't
+/// match, the actual CWD is returned, falling back to the stored initial
+/// CWD if it can't be read.
+///
+/// zsh emits OSC 7 when configured with, e.g.:
+///
+/// ```zsh
+/// _osc7_cwd() { printf '\e]7;file://%s%s\e\\' "$HOST" "${PWD// /%20}" }
+/// autoload -Uz add-zsh-hook && add-zsh-hook chpwd _osc7_cwd && _osc7_cwd
+/// ```
+///
+/// bash can do the same from `PROMPT_COMMAND`; fish emits it by default.
+///
+/// The path is returned as reported, so a directory entered through a
+/// symlink keeps its logical path when OSC 7 is available. With
+/// `resolve_symlinks`, it's canonicalized instead, falling back to the
+/// unresolved path if that fails (e.g. the directory was removed).
+#[tauri::command]
+pub fn get_cwd(
+    app: AppHandle,
+    session_id: String,
+    resolve_symlinks: Option<bool>,
+) -> Result<String, CommandError> {
+    let cwd = session_cwd(&app, &session_id)?;
+    if !resolve_symlinks.unwrap_or(false) {
+        return Ok(cwd);
+    }
+    Ok(std::fs::canonicalize(&cwd)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or(cwd))
+}
+
+/// Get the current working directory of every session, by session ID.
+///
+/// Resolved like `get_cwd`, but the live lookups still needed are batched
+/// into a single `lsof` call on macOS.
+#[tauri::command]
+pub fn get_cwd_all(app: AppHandle) -> HashMap<String, String> {
+    let snapshot: Vec<(String, u32, String, Arc<SessionOutput>)> = app
+        .state::<PtyManager>()
+        .sessions
+        .lock()
+        .iter()
+        .map(|(id, session)| {
+            let session_lock = session.lock();
+            (
+                id.clone(),
+                session_lock.child_id,
+                session_lock.cwd.clone(),
+                session_lock.output.clone(),
+            )
+        })
+        .collect();
+
+    let mut cwds = HashMap::new();
+    let mut unresolved = Vec::new();
+    for (session_id, pid, fallback, output) in snapshot {
+        match cached_cwd(&output) {
+            Some(cwd) => {
+                cwds.insert(session_id, cwd);
+            }
+            None => unresolved.push((session_id, pid, fallback, output)),
+        }
+    }
+
+    let pids: Vec<u32> = unresolved
+        .iter()
+        .filter(|(_, pid, _, _)| *pid > 0)
+        .map(|(_, pid, _, _)| *pid)
+        .collect();
+    let mut live = get_process_cwds(&pids);
+
+    for (session_id, pid, fallback, output) in unresolved {
+        let cwd = check_reported_cwd(&output, live.remove(&pid), fallback);
+        cwds.insert(session_id, cwd);
+    }
+    cwds
+}
+
+fn session_cwd(app: &AppHandle, session_id: &str) -> Result<String, CommandError> {
+    let session = app.state::<PtyManager>().get_session(session_id)?;
+    let (pid, fallback, output) = {
+        let session_lock = session.lock();
+        (
+            session_lock.child_id,
+            session_lock.cwd.clone(),
+            session_lock.output.clone(),
+        )
+    };
+
+    if let Some(cwd) = cached_cwd(&output) {
+        return Ok(cwd);
+    }
+    let live = if pid > 0 { get_process_cwd(pid) } else { None };
+    Ok(check_reported_cwd(&output, live, fallback))
+}
+
+/// The cwd chosen when the session's current OSC 7 report was last
+/// checked, if it hasn't changed since. Always `None` on Linux, where
+/// reading `/proc` is cheap enough to check every time.
+fn cached_cwd(output: &SessionOutput) -> Option<String> {
+    if cfg!(target_os = "linux") {
+        return None;
+    }
+    let reported = output.reported_cwd.lock().clone()?;
+    match &*output.checked_cwd.lock() {
+        Some((checked, cwd)) if *checked == reported => Some(cwd.clone()),
+        _ => None,
+    }
+}
+
+/// Choose the session's cwd given its process's real cwd, remembering the
+/// choice for the current OSC 7 report.
+fn check_reported_cwd(output: &SessionOutput, live: Option<String>, fallback: String) -> String {
+    let reported = output.reported_cwd.lock().clone();
+    let cwd = choose_cwd(reported.clone(), live, fallback);
+    if let Some(reported) = reported {
+        *output.checked_cwd.lock() = Some((reported, cwd.clone()));
+    }
+    cwd
+}
+
+/// Pick a session's cwd from its OSC 7 report and the process's real cwd.
+///
+/// The report keeps a symlinked directory's logical path, but any output
+/// can set it, so it's only used when it resolves to the real cwd or the
+/// real cwd is unknown.
+fn choose_cwd(reported: Option<String>, live: Option<String>, fallback: String) -> String {
+    match (reported, live) {
+        (Some(reported), Some(live)) => {
+            let same = std::fs::canonicalize(&reported)
+                .is_ok_and(|path| path == std::path::Path::new(&live));
+            if same {
+                reported
+            } else {
+                live
+            }
+        }
+        (Some(reported), None) => reported,
+        (None, Some(live)) => live,
+        (None, None) => fallback,
+    }
+}
+
+/// How long `lsof` may take before `get_cwd` and `get_cwd_all` fall back
+/// to the stored cwd.
+#[cfg(target_os = "macos")]
+const LSOF_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Query the actual CWD of a process. See `get_process_cwds`.
+fn get_process_cwd(pid: u32) -> Option<String> {
+    get_process_cwds(&[pid]).remove(&pid)
+}
+
+/// Query the actual CWDs of several processes in one pass. Processes whose
+/// CWD can't be read are left out.
+/// On macOS: uses one `lsof -a -p <pid>,<pid>... -d cwd -Fn`, killed after 500 ms
+/// On Linux: reads each `/proc/<pid>/cwd` symlink
+fn get_process_cwds(pids: &[u32]) -> HashMap<u32, String> {
+    #[cfg(target_os = "macos")]
+    {
+        if pids.is_empty() {
+            return HashMap::new();
+        }
+        let pid_list = pids
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        let output = process::output_with_timeout(
+            std::process::Command::new("lsof").args(["-a", "-p", &pid_list, "-d", "cwd", "-Fn"]),
+            LSOF_TIMEOUT,
+        );
+        match output {
+            Ok(Some(output)) => parse_lsof_cwds(&String::from_utf8_lossy(&output.stdout)),
+            _ => HashMap::new(),
+        }
+    }
+    #[cfg(target_os = "linux")]
+    {
+        pids.iter()
+            .filter_map(|&pid| {
+                // Fails with ENOENT once the process has exited; a cwd removed
+                // underneath the process reads back with a " (deleted)" suffix.
+                let path = std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()?;
+                let path = path.to_string_lossy();
+                if !path.starts_with('/') || path.ends_with(" (deleted)") {
+                    return None;
+                }
+                Some((pid, path.to_string()))
+            })
+            .collect()
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        let _ = pids;
+        HashMap::new()
+    }
+}
+
+/// Split `lsof -Fn` output by process: each process's fields follow its
+/// `p<pid>` line, and the `n<path>` line holds the path.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_lsof_cwds(stdout: &str) -> HashMap<u32, String> {
+    let mut cwds = HashMap::new();
+    let mut pid = None;
+    for line in stdout.lines() {
+        if let Some(value) = line.strip_prefix('p') {
+            pid = value.parse().ok();
+        } else if let (Some(path), Some(pid)) = (line.strip_prefix('n'), pid) {
+            if path.starts_with('/') {
+                cwds.entry(pid).or_insert_with(|| path.to_string());
+            }
+        }
+    }
+    cwds
+}
+
+/// Get system information for AI context.
+///
+/// OS, architecture and memory are detected at runtime; `distro` is only
+/// set on Linux.
+#[tauri::command]
+pub fn get_system_info() -> Result<serde_json::Value, CommandError> {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "unknown".to_string());
+    let home = dirs::home_dir()
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+
+    let os = system::os_details();
+    let memory = system::memory_details();
+
+    Ok(serde_json::json!({
+        "os": os.name,
+        "osVersion": os.version,
+        "kernel": os.kernel,
+        "distro": os.distro,
+        "arch": os.arch,
+        "cpuCores": system::cpu_cores(),
+        "memoryTotal": memory.total,
+        "memoryAvailable": memory.available,
+        "shell": shell,
+        "home": home,
+        "user": user,
+        "term": TERM,
+    }))
+}
+
+/// Get usage of every mounted filesystem (parsed `df -kP`) for AI context.
+#[tauri::command]
+pub fn get_disk_usage() -> Result<Vec<system::DiskUsage>, CommandError> {
+    system::disk_usage().map_err(|e| CommandError::io("Failed to read disk usage", e))
+}
+
+/// Get the total, free and available bytes on the filesystem that holds
+/// `path`, e.g. to warn before a command that writes a lot. `path` may
+/// start with `~` and must be absolute; if it doesn't exist yet, its
+/// nearest existing ancestor is measured and returned as `path`.
+#[tauri::command]
+pub fn get_free_space(path: String) -> Result<system::FreeSpace, CommandError> {
+    let path = expand_tilde(&path)?;
+    if !path.is_absolute() {
+        return Err(CommandError::InvalidInput(format!(
+            "Path must be absolute: {}",
+            path.display()
+        )));
+    }
+    system::free_space(&path).map_err(|e| CommandError::io("Failed to read free space", e))
+}
+
+/// Entries of a directory with `ls -la`-style details, read from file
+/// metadata rather than by running `ls`. Includes hidden files; symlinks
+/// are described rather than followed. At most 10,000 entries are read.
+#[tauri::command]
+pub fn get_directory_listing_detailed(path: String) -> Result<serde_json::Value, CommandError> {
+    let target = expand_tilde(&path)?;
+    let dir_entries = std::fs::read_dir(&target)
+        .map_err(|e| CommandError::io(&format!("Failed to read directory {}", path), e))?;
+
+    let mut entries = Vec::new();
+    let mut truncated = false;
+    for entry in dir_entries.flatten() {
+        if entries.len() >= MAX_DIRECTORY_SCAN {
+            truncated = true;
+            break;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Entries can vanish between listing and stat; skip them.
+        if let Ok(details) = system::describe_dir_entry(name, &entry.path()) {
+            entries.push(details);
+        }
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(serde_json::json!({
+        "path": target.to_string_lossy(),
+        "entries": entries,
+        "truncated": truncated,
+    }))
+}
+
+/// Default and maximum bytes returned by `read_file_for_context`.
+const DEFAULT_CONTEXT_FILE_BYTES: usize = 64 * 1024;
+const MAX_CONTEXT_FILE_BYTES: usize = 1024 * 1024;
+
+/// Read a file the user referenced, for AI context, without going through
+/// the shell.
+///
+/// `path` must be absolute or start with `~`, and resolve (after following
+/// symlinks) to a regular file inside the home directory or, on macOS,
+/// `/Volumes`. At most `max_bytes` (default 64 KiB, capped at 1 MiB) are
+/// returned, with secrets redacted. Binary files are flagged with
+/// `is_binary` and their content is left empty.
+#[tauri::command]
+pub fn read_file_for_context(
+    path: String,
+    max_bytes: Option<usize>,
+) -> Result<system::FileContents, CommandError> {
+    let target = expand_tilde(&path)?;
+    let resolved = external::resolve_user_path(&target, &external::user_roots())
+        .map_err(CommandError::InvalidInput)?;
+    if !resolved.is_file() {
+        return Err(CommandError::InvalidInput(format!("Not a file: {}", path)));
+    }
+
+    let max_bytes = max_bytes
+        .unwrap_or(DEFAULT_CONTEXT_FILE_BYTES)
+        .min(MAX_CONTEXT_FILE_BYTES);
+    let mut contents = system::read_text_file(&resolved, max_bytes)
+        .map_err(|e| CommandError::io(&format!("Failed to read {}", path), e))?;
+    contents.content = redact_secrets(&contents.content);
+
+    log::info!(
+        "Read {} for AI context ({} bytes, truncated: {}, binary: {})",
+        resolved.display(),
+        contents.content.len(),
+        contents.truncated,
+        contents.is_binary
+    );
+    Ok(contents)
+}
+
+/// Default and maximum number of processes returned by `get_running_processes`.
+const DEFAULT_PROCESS_LIMIT: usize = 10;
+const MAX_PROCESS_LIMIT: usize = 200;
+
+/// The processes using the most CPU, then memory, for AI context.
+///
+/// CPU usage is recent on macOS and a lifetime average on Linux.
+#[tauri::command]
+pub fn get_running_processes(
+    limit: Option<usize>,
+) -> Result<Vec<process::ProcessRow>, CommandError> {
+    let limit = limit
+        .unwrap_or(DEFAULT_PROCESS_LIMIT)
+        .min(MAX_PROCESS_LIMIT);
+    let mut rows = process::process_table().map_err(|e| match e.kind() {
+        std::io::ErrorKind::Unsupported => CommandError::NotSupported(e.to_string()),
+        _ => CommandError::io("Failed to list processes", e),
+    })?;
+    rows.sort_by(|a, b| {
+        let cpu = |row: &process::ProcessRow| row.cpu_percent.unwrap_or(0.0);
+        cpu(b)
+            .total_cmp(&cpu(a))
+            .then_with(|| b.memory_bytes.cmp(&a.memory_bytes))
+    });
+    rows.truncate(limit);
+    Ok(rows)
+}
+
+/// How long `get_app_metrics` samples CPU usage for.
+const APP_CPU_SAMPLE: Duration = Duration::from_millis(100);
+
+/// The app's own resource usage, from `get_app_metrics`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AppMetrics {
+    #[serde(flatten)]
+    pub usage: process::OwnUsage,
+    /// Sessions the `PtyManager` is tracking.
+    pub session_count: usize,
+    /// Of those, sessions whose reader thread is still running.
+    pub reader_threads: usize,
+}
+
+/// Report the app's own CPU, memory, thread and file descriptor usage and
+/// its session count, for diagnosing leaks such as threads or PTYs left
+/// behind by sessions that weren't cleaned up.
+///
+/// CPU usage is sampled over 100 ms, so the call takes that long. Fields
+/// the platform can't report are `null`.
+#[tauri::command(async)]
+pub fn get_app_metrics(app: AppHandle) -> AppMetrics {
+    let outputs: Vec<Arc<SessionOutput>> = app
+        .state::<PtyManager>()
+        .sessions
+        .lock()
+        .values()
+        .map(|session| session.lock().output.clone())
+        .collect();
+    AppMetrics {
+        usage: process::own_usage(APP_CPU_SAMPLE),
+        session_count: outputs.len(),
+        reader_threads: outputs
+            .iter()
+            .filter(|output| output.reader_running.load(Ordering::Acquire))
+            .count(),
+    }
+}
+
+/// Upper bound on directory entries read by `list_directory`, so huge
+/// directories can't stall autocomplete.
+const MAX_DIRECTORY_SCAN: usize = 10_000;
+
+/// Hide dotfiles by default unless the user explicitly typed a dot prefix.
+/// We infer this from the last path component in the *typed* string.
+fn shows_hidden(typed: &str) -> bool {
+    let typed = typed.trim_end_matches('/');
+    let last_component = typed.rsplit('/').next().unwrap_or(typed);
+    last_component.starts_with('.')
+}
+
+/// Snapshot a directory for `snapshot_directory`, treating a missing path
+/// or non-directory as empty like `list_directory` does.
+fn snapshot_directory_at(path: &str) -> Result<system::DirectorySnapshot, CommandError> {
+    let target = expand_tilde(path)?;
+    if !target.is_dir() {
+        return Ok(system::DirectorySnapshot::default());
+    }
+    system::snapshot_dir(&target, shows_hidden(path), MAX_DIRECTORY_SCAN)
+        .map_err(|e| CommandError::io("Failed to read directory", e))
+}
+
+/// Record each entry of a directory as name → `{ size, modified, is_dir }`
+/// so a later `diff_directory_snapshot` can tell what changed.
+///
+/// `path` is expanded and dotfiles are hidden as in `list_directory`. At
+/// most `MAX_DIRECTORY_SCAN` entries are read, and `truncated` is set when
+/// the directory has more.
+#[tauri::command]
+pub fn snapshot_directory(path: String) -> Result<system::DirectorySnapshot, CommandError> {
+    snapshot_directory_at(&path)
+}
+
+/// Changes to a directory since `previous` (the `entries` of an earlier
+/// snapshot), from `diff_directory_snapshot`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DirectoryChanges {
+    #[serde(flatten)]
+    pub diff: system::SnapshotDiff,
+    /// The current snapshot, to pass as `previous` next time.
+    pub snapshot: system::DirectorySnapshot,
+}
+
+/// Compare a directory against an earlier `snapshot_directory` result and
+/// list the entry names `added`, `removed` and `modified` (size,
+/// modification time or type changed) since then.
+#[tauri::command]
+pub fn diff_directory_snapshot(
+    path: String,
+    previous: HashMap<String, system::SnapshotEntry>,
+) -> Result<DirectoryChanges, CommandError> {
+    let snapshot = snapshot_directory_at(&path)?;
+    let diff = system::diff_snapshots(&previous, &snapshot.entries);
+    Ok(DirectoryChanges { diff, snapshot })
+}
+
+/// List files and directories in a given path for autocomplete.
+/// Returns entries with name, path, and whether they are a directory.
+///
+/// `pattern` is a glob (e.g. `*.rs`) matched against entry names. With
+/// `respect_gitignore`, entries ignored by `.gitignore` files (including
+/// parent directories within the repo) or `.git/info/exclude` are omitted.
+///
+/// `offset`/`limit` select a page of the sorted listing; `total` is the
+/// number of entries before paging. At most `MAX_DIRECTORY_SCAN` entries
+/// are read, and `truncated` is set when the directory has more.
+///
+/// With `query`, only entries whose name contains the query as a fuzzy
+/// subsequence are returned, best match first, each with a `score`.
+#[tauri::command]
+pub fn list_directory(
+    path: String,
+    pattern: Option<String>,
+    respect_gitignore: Option<bool>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    query: Option<String>,
+) -> Result<serde_json::Value, CommandError> {
+    use std::path::Path;
+
+    let matcher = pattern
+        .as_deref()
+        .map(|p| {
+            globset::Glob::new(p)
+                .map(|glob| glob.compile_matcher())
+                .map_err(|e| CommandError::InvalidInput(format!("Invalid pattern: {}", e)))
+        })
+        .transpose()?;
+
+    let show_hidden = shows_hidden(&path);
+
+    let target = expand_tilde(&path)?;
+
+    if !target.exists() {
+        return Ok(serde_json::json!({ "entries": [], "path": path, "total": 0 }));
+    }
+
+    if !target.is_dir() {
+        return Ok(serde_json::json!({ "entries": [], "path": path, "total": 0 }));
+    }
+
+    struct DirEntry {
+        name: String,
+        name_lower: String,
+        path: String,
+        is_dir: bool,
+        score: Option<i64>,
+    }
+
+    let mut entries: Vec<DirEntry> = Vec::new();
+    let mut truncated = false;
+    let mut push_entry = |name: String, full_path: &Path, is_dir: bool| {
+        // Skip hidden files unless the user explicitly typed a dot prefix
+        if !show_hidden && name.starts_with('.') {
+            return;
+        }
+        if matcher.as_ref().is_some_and(|m| !m.is_match(&name)) {
+            return;
+        }
+        let score = match query.as_deref() {
+            Some(q) => match fuzzy_score(q, &name) {
+                Some(score) => Some(score),
+                None => return,
+            },
+            None => None,
+        };
+        entries.push(DirEntry {
+            name_lower: name.to_lowercase(),
+            name,
+            path: full_path.to_string_lossy().to_string(),
+            is_dir,
+            score,
+        });
+    };
+
+    if respect_gitignore.unwrap_or(false) {
+        let walker = ignore::WalkBuilder::new(&target)
+            .max_depth(Some(1))
+            .standard_filters(false)
+            .parents(true)
+            .git_ignore(true)
+            .git_exclude(true)
+            .build();
+        for (scanned, entry) in walker.flatten().filter(|e| e.depth() > 0).enumerate() {
+            if scanned >= MAX_DIRECTORY_SCAN {
+                truncated = true;
+                break;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            push_entry(name, entry.path(), is_dir);
+        }
+    } else {
+        let read_dir = std::fs::read_dir(&target)
+            .map_err(|e| CommandError::io("Failed to read directory", e))?;
+        for (scanned, entry) in read_dir.flatten().enumerate() {
+            if scanned >= MAX_DIRECTORY_SCAN {
+                truncated = true;
+                break;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            push_entry(name, &entry.path(), is_dir);
+        }
+    }
+
+    // Sort: best score first (when querying), then directories, then alphabetically
+    entries.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| match (a.is_dir, b.is_dir) {
+                (true, false) => std::cmp::Ordering::Less,
+                (false, true) => std::cmp::Ordering::Greater,
+                _ => a.name_lower.cmp(&b.name_lower),
+            })
+    });
+
+    let total = entries.len();
+    let json_entries: Vec<serde_json::Value> = entries
+        .into_iter()
+        .skip(offset.unwrap_or(0))
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|e| {
+            let mut entry = serde_json::json!({
+                "name": e.name,
+                "path": e.path,
+                "isDir": e.is_dir,
+            });
+            if let Some(score) = e.score {
+                entry["score"] = score.into();
+            }
+            entry
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "entries": json_entries,
+        "path": target.to_string_lossy().to_string(),
+        "total": total,
+        "truncated": truncated,
+    }))
+}
+
+/// Score `candidate` against `query` as a case-insensitive subsequence.
+///
+/// Returns `None` if not every query character appears in order. Matches
+/// at the start of the name or of a word (after `.`, `-`, `_`, space) and
+/// runs of consecutive matches score higher; skipped characters cost a
+/// little, so shorter, tighter matches win.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let mut score = 0i64;
+    let mut qi = 0;
+    let mut prev_match: Option<usize> = None;
+    let mut prev_char: Option<char> = None;
+    for (ci, ch) in candidate.chars().enumerate() {
+        if qi < query.len() && ch.to_lowercase().eq(std::iter::once(query[qi])) {
+            score += 10;
+            if ci == 0 {
+                score += 15;
+            } else if prev_char.is_some_and(|p| matches!(p, '.' | '-' | '_' | ' ')) {
+                score += 10;
+            }
+            if prev_match.is_some_and(|p| p + 1 == ci) {
+                score += 8;
+            }
+            prev_match = Some(ci);
+            qi += 1;
+        } else {
+            score -= 1;
+        }
+        prev_char = Some(ch);
+    }
+
+    (qi == query.len()).then_some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::PolicyMode;
+    use crate::pty_backend::mock::MockBackend;
+
+    #[test]
+    fn utf8_decoder_joins_sequences_split_across_reads() {
+        let text = "box ─ and emoji 🚀 done";
+        let bytes = text.as_bytes();
+        let rocket = text.find('🚀').unwrap();
+
+        // Split in the middle of the 4-byte emoji.
+        let (first, second) = bytes.split_at(rocket + 2);
+        let mut decoder = Utf8Decoder::default();
+        let mut out = decoder.decode(first);
+        out.push_str(&decoder.decode(second));
+
+        assert!(!out.contains(char::REPLACEMENT_CHARACTER));
+        assert_eq!(out, text);
+    }
+
+    #[test]
+    fn utf8_decoder_replaces_invalid_bytes() {
+        let mut decoder = Utf8Decoder::default();
+        let out = decoder.decode(b"ok\xffok");
+        assert_eq!(out, "ok\u{FFFD}ok");
+        assert!(decoder.carry.is_empty());
+    }
+
+    #[test]
+    fn utf8_decoder_flushes_a_truncated_character() {
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(
+            decoder.decode("ok€".as_bytes().split_last().unwrap().1),
+            "ok"
+        );
+        assert_eq!(decoder.finish(), "\u{FFFD}");
+        assert_eq!(decoder.finish(), "");
+    }
+
+    #[test]
+    fn scrollback_reports_wrapping() {
+        let mut buffer = ScrollbackBuffer::new(8);
+        buffer.push(b"abcd");
+        assert!(!buffer.wrapped);
+        buffer.push(b"efghij");
+        assert!(buffer.wrapped);
+        assert_eq!(buffer.tail(usize::MAX), b"cdefghij");
+    }
+
+    #[test]
+    fn line_buffer_releases_whole_lines() {
+        let mut lines = LineBuffer::default();
+        assert_eq!(lines.push(b"ab"), None);
+        assert_eq!(lines.push(b"c\r\nde\nf"), Some(b"abc\r\nde\n".to_vec()));
+        assert_eq!(lines.take(), Some(b"f".to_vec()));
+        assert_eq!(lines.take(), None);
+
+        let long = vec![b'x'; LINE_HOLD_MAX + 1];
+        assert_eq!(lines.push(&long).map(|out| out.len()), Some(long.len()));
+        assert_eq!(lines.take(), None);
+    }
+
+    #[test]
+    fn finds_matches_by_line() {
+        let text = "$ make\r\nerror: one\r\nok\nAnother Error: two";
+        let found = find_line_matches(text, "error", false);
+        let positions: Vec<(usize, usize)> = found.iter().map(|m| (m.line, m.offset)).collect();
+        assert_eq!(positions, vec![(1, 0), (3, 8)]);
+        assert_eq!(found[0].text, "error: one");
+
+        assert_eq!(find_line_matches(text, "Error", true).len(), 1);
+    }
+
+    #[test]
+    fn cleans_colored_output_and_titles() {
+        let text = "\x1b]0;me@host: ~/src\x07$ ls --color\r\n\
+                    \x1b[0m\x1b[01;34mdocs\x1b[0m\t\x1b[01;32mbuild.sh\x1b[0m\r\n\
+                    README.md\r\n\x1b]0;me@host: ~/src\x1b\\$ ";
+        assert_eq!(
+            clean_lines(text, 0, usize::MAX),
+            "$ ls --color\ndocs\tbuild.sh\nREADME.md\n$ "
+        );
+        assert_eq!(clean_lines(text, 1, 3), "docs\tbuild.sh\nREADME.md");
+        assert_eq!(clean_lines(text, 9, 12), "");
+    }
+
+    #[test]
+    fn parses_env_output() {
+        let text = "env\r\nHOME=/home/me\r\nPATH=/usr/bin:/bin\r\n\
+                    OPENAI_API_KEY=sk-abcdefghijklmnopqrstu\r\nEMPTY=\r\n\
+                    second line of a value\r\n$ ";
+        let env = parse_env_output(text);
+        assert_eq!(env.len(), 4);
+        assert_eq!(env["HOME"], "/home/me");
+        assert_eq!(env["PATH"], "/usr/bin:/bin");
+        assert_eq!(env["EMPTY"], "");
+        assert!(!env["OPENAI_API_KEY"].contains("abcdefghijklmnop"));
+    }
+
+    #[test]
+    fn validates_working_directory() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        assert_eq!(
+            resolve_working_dir(dir.to_str(), false).unwrap(),
+            dir.to_string_lossy()
+        );
+
+        let missing = dir.join(format!("missing-{}", Uuid::new_v4()));
+        assert!(resolve_working_dir(missing.to_str(), false).is_err());
+        assert_eq!(
+            resolve_working_dir(missing.to_str(), true).unwrap(),
+            home_dir_string()
+        );
+        assert_eq!(resolve_working_dir(None, false).unwrap(), home_dir_string());
+    }
+
+    #[test]
+    fn fuzzy_scores_subsequences() {
+        assert!(fuzzy_score("cfg", "Cargo.config").is_some());
+        assert_eq!(fuzzy_score("xyz", "Cargo.toml"), None);
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+
+        // Prefix and consecutive matches beat scattered ones.
+        let prefix = fuzzy_score("car", "Cargo.toml").unwrap();
+        let scattered = fuzzy_score("car", "some_crate_archive").unwrap();
+        assert!(prefix > scattered);
+
+        // Word-boundary matches beat mid-word ones.
+        let boundary = fuzzy_score("conf", "tauri.conf.json").unwrap();
+        let inner = fuzzy_score("conf", "reconfigure.json").unwrap();
+        assert!(boundary > inner);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolves_programs_from_path() {
+        assert_eq!(resolve_program("sh", "relative:/bin").unwrap(), "/bin/sh");
+        assert_eq!(resolve_program("/bin/sh", "").unwrap(), "/bin/sh");
+        assert!(resolve_program("bin/sh", "/").is_err());
+        assert!(resolve_program("no-such-program-xyz", "/bin").is_err());
+    }
+
+    /// Collects session events as `"<kind>:<detail>"` strings.
+    #[derive(Default)]
+    struct RecordedEvents(Mutex<Vec<String>>);
+
+    impl SessionEvents for RecordedEvents {
+        fn send(&self, event: SessionEvent) {
+            let recorded = match event {
+                SessionEvent::Output(payload) => format!("output:{}", payload.data),
+                SessionEvent::Title(payload) => format!("title:{}", payload.title),
+                SessionEvent::ClipboardRequest(payload) => format!("clipboard:{}", payload.data),
+                SessionEvent::ClipboardWrite(text) => format!("clipboard-write:{}", text),
+                SessionEvent::Command(payload) => {
+                    format!("command:{:?}:{:?}", payload.command_text, payload.exit_code)
+                }
+                SessionEvent::Exit(payload) => format!("exit:{:?}", payload.exit_code),
+            };
+            self.0.lock().push(recorded);
+        }
+    }
+
+    impl RecordedEvents {
+        fn count(&self, recorded: impl Fn(&str) -> bool) -> usize {
+            self.0.lock().iter().filter(|e| recorded(e)).count()
+        }
+    }
+
+    fn mock_request() -> SpawnRequest {
+        SpawnRequest {
+            program: "/bin/sh".to_string(),
+            args: Vec::new(),
+            rows: Some(24),
+            cols: Some(80),
+            cwd: None,
+            env_vars: None,
+            scrollback_bytes: None,
+            encoding: OutputEncoding::Utf8,
+            max_output_rate: None,
+            clean_env: false,
+            idle_timeout: None,
+            cwd_fallback: false,
+            audited: true,
+            normalize_input: false,
+            line_buffered: false,
+        }
+    }
+
+    fn wait_until(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn unknown_sessions_are_not_found() {
+        let manager = PtyManager::with_backend(Arc::new(MockBackend::default()));
+        let not_found = CommandError::session_not_found("missing");
+
+        assert_eq!(manager.write("missing", b"ls\n"), Err(not_found.clone()));
+        assert_eq!(manager.resize("missing", 40, 120), Err(not_found.clone()));
+        assert_eq!(manager.kill("missing", DEFAULT_KILL_GRACE), Err(not_found));
+    }
+
+    #[test]
+    fn session_lifecycle_against_mock_backend() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let events = Arc::new(RecordedEvents::default());
+
+        let session_id = manager.spawn(events.clone(), mock_request()).unwrap();
+        let pty = backend.take_spawned().unwrap();
+        assert!(manager.get_session(&session_id).is_ok());
+
+        manager.write(&session_id, b"echo hi\n").unwrap();
+        assert_eq!(pty.input.lock().as_slice(), b"echo hi\n");
+
+        manager.resize(&session_id, 40, 120).unwrap();
+        assert_eq!((pty.size.lock().rows, pty.size.lock().cols), (40, 120));
+
+        pty.output.send(b"hi\r\n".to_vec()).unwrap();
+        assert!(wait_until(|| events.count(|e| e == "output:hi\r\n") == 1));
+
+        pty.output.send(b"bye\r\n".to_vec()).unwrap();
+        pty.exit.send(3).unwrap();
+        drop(pty);
+        assert!(wait_until(|| manager.get_session(&session_id).is_err()));
+        assert!(wait_until(|| events.count(|e| e.starts_with("exit:")) > 0));
+        assert_eq!(events.0.lock()[1..], ["output:bye\r\n", "exit:Some(3)"]);
+    }
+
+    #[test]
+    fn debounced_resizes_apply_only_the_last_size() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let events = Arc::new(RecordedEvents::default());
+
+        let session_id = manager.spawn(events, mock_request()).unwrap();
+        let pty = backend.take_spawned().unwrap();
+        let initial = *pty.size.lock();
+
+        for cols in 81..=90 {
+            manager.resize_debounced(&session_id, 30, cols).unwrap();
+        }
+        assert_eq!(*pty.size.lock(), initial);
+        assert!(wait_until(|| pty.size.lock().cols == 90));
+        assert_eq!(pty.size.lock().rows, 30);
+
+        // An immediate resize supersedes one still pending.
+        manager.resize_debounced(&session_id, 10, 10).unwrap();
+        manager.resize(&session_id, 50, 150).unwrap();
+        thread::sleep(RESIZE_DEBOUNCE * 3);
+        assert_eq!((pty.size.lock().rows, pty.size.lock().cols), (50, 150));
+    }
+
+    #[test]
+    fn tracks_osc133_command_marks() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let events = Arc::new(RecordedEvents::default());
+
+        let session_id = manager.spawn(events.clone(), mock_request()).unwrap();
+        let pty = backend.take_spawned().unwrap();
+        // Split mid-sequence to exercise offsets across reads.
+        pty.output
+            .send(b"\x1b]133;A\x07$ \x1b]133;B\x07ls\x1b[K -l".to_vec())
+            .unwrap();
+        pty.output
+            .send(b"a\r\n\x1b]133;C\x07file\r\n\x1b]13".to_vec())
+            .unwrap();
+        pty.output.send(b"3;D;2\x07".to_vec()).unwrap();
+        assert!(wait_until(
+            || events.count(|e| e.starts_with("command:")) == 1
+        ));
+        assert_eq!(events.count(|e| e == "command:Some(\"ls -la\"):Some(2)"), 1);
+
+        let session = manager.get_session(&session_id).unwrap();
+        let output = session.lock().output.clone();
+        let marks = output.command_marks.lock();
+        assert_eq!(marks.len(), 1);
+        assert_eq!(marks[0].prompt_start, 8);
+        assert_eq!(marks[0].command_start, Some(18));
+        assert_eq!(marks[0].output_start, Some(37));
+        assert_eq!(marks[0].end, Some(53));
+        assert_eq!(output.exit_codes.lock().back(), Some(&2));
+    }
+
+    #[test]
+    fn prompt_state_follows_osc133_marks() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let session_id = manager
+            .spawn(Arc::new(RecordedEvents::default()), mock_request())
+            .unwrap();
+        let pty = backend.take_spawned().unwrap();
+        let session = manager.get_session(&session_id).unwrap();
+        let output = session.lock().output.clone();
+        let marks = || output.command_marks.lock().len();
+
+        // No marks and no foreground process group to compare against.
+        assert!(!manager.prompt_state(&session_id).unwrap().at_prompt);
+
+        pty.output
+            .send(b"\x1b]133;A\x07$ \x1b]133;B\x07".to_vec())
+            .unwrap();
+        assert!(wait_until(|| marks() == 1));
+        assert!(manager.prompt_state(&session_id).unwrap().at_prompt);
+
+        pty.output.send(b"vim\r\n\x1b]133;C\x07".to_vec()).unwrap();
+        assert!(wait_until(|| !manager
+            .prompt_state(&session_id)
+            .unwrap()
+            .at_prompt));
+
+        pty.output.send(b"\x1b]133;D;0\x07".to_vec()).unwrap();
+        assert!(wait_until(|| manager
+            .prompt_state(&session_id)
+            .unwrap()
+            .at_prompt));
+    }
+
+    #[test]
+    fn replays_cast_output_and_stops_on_request() {
+        let manager = PtyManager::with_backend(Arc::new(MockBackend::default()));
+        let cast = recording::parse_cast(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+             [0.1, \"o\", \"$ ls\\r\\n\"]\n\
+             [0.2, \"r\", \"100x30\"]\n\
+             [0.3, \"o\", \"file\\r\\n\"]\n",
+        )
+        .unwrap();
+
+        let events = Arc::new(RecordedEvents::default());
+        let replay_id = manager.replay(events.clone(), cast, 10.0);
+        assert!(wait_until(|| events.count(|e| e.starts_with("exit:")) == 1));
+        assert_eq!(
+            *events.0.lock(),
+            ["output:$ ls\r\n", "output:file\r\n", "exit:Some(0)"]
+        );
+        assert!(manager.stop_replay(&replay_id).is_err());
+
+        let slow = vec![CastEvent(60.0, "o".to_string(), "late".to_string())];
+        let events = Arc::new(RecordedEvents::default());
+        let replay_id = manager.replay(events.clone(), slow, 1.0);
+        manager.stop_replay(&replay_id).unwrap();
+        assert!(wait_until(|| events.count(|e| e == "exit:None") == 1));
+        assert_eq!(events.count(|e| e.starts_with("output:")), 0);
+    }
+
+    #[test]
+    fn captures_output_with_and_without_shell_integration() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let session_id = manager
+            .spawn(Arc::new(RecordedEvents::default()), mock_request())
+            .unwrap();
+        let pty = backend.take_spawned().unwrap();
+        let timeout = Duration::from_secs(5);
+
+        // No marks yet: a sentinel is appended and echoed back by the "shell".
+        let captured = thread::scope(|scope| {
+            let run = scope.spawn(|| manager.run_and_capture(&session_id, "ls", timeout));
+            assert!(wait_until(|| pty.input.lock().ends_with(b"\n")));
+            let typed = String::from_utf8(pty.input.lock().clone()).unwrap();
+            let id = typed
+                .split("done_")
+                .nth(1)
+                .unwrap()
+                .split(' ')
+                .next()
+                .unwrap();
+            let echo = typed.replace('\n', "\r\n");
+            let reply = format!("{}a.txt\r\nb.txt\r\n\r\n__ait_done_{}:2\r\n$ ", echo, id);
+            pty.output.send(reply.into_bytes()).unwrap();
+            run.join().unwrap().unwrap()
+        });
+        assert_eq!(captured.output, "a.txt\nb.txt");
+        assert_eq!(captured.exit_code, Some(2));
+        assert!(!captured.timed_out);
+
+        // With OSC 133 marks, the C..D region is returned.
+        pty.input.lock().clear();
+        pty.output
+            .send(b"\x1b]133;A\x07$ \x1b]133;B\x07".to_vec())
+            .unwrap();
+        let session = manager.get_session(&session_id).unwrap();
+        let output = session.lock().output.clone();
+        assert!(wait_until(|| !output.command_marks.lock().is_empty()));
+        let captured = thread::scope(|scope| {
+            let run = scope.spawn(|| manager.run_and_capture(&session_id, "pwd", timeout));
+            assert!(wait_until(|| pty.input.lock().as_slice() == b"pwd\n"));
+            pty.output
+                .send(b"pwd\r\n\x1b]133;C\x07/tmp\r\n\x1b]133;D;0\x07".to_vec())
+                .unwrap();
+            run.join().unwrap().unwrap()
+        });
+        assert_eq!(captured.output, "/tmp");
+        assert_eq!(captured.exit_code, Some(0));
+
+        let timed_out = manager
+            .run_and_capture(&session_id, "sleep 9", Duration::from_millis(50))
+            .unwrap();
+        assert!(timed_out.timed_out);
+        assert_eq!(timed_out.exit_code, None);
+    }
+
+    #[test]
+    fn normalize_input_composes_text_to_nfc() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let type_into = |request: SpawnRequest| {
+            let session_id = manager
+                .spawn(Arc::new(RecordedEvents::default()), request)
+                .unwrap();
+            let pty = backend.take_spawned().unwrap();
+            let data = manager
+                .normalized_input(&session_id, "e\u{301}".to_string())
+                .unwrap();
+            manager.write(&session_id, data.as_bytes()).unwrap();
+            let input = pty.input.lock().clone();
+            String::from_utf8(input).unwrap()
+        };
+
+        let normalized = type_into(SpawnRequest {
+            normalize_input: true,
+            ..mock_request()
+        });
+        assert_eq!(normalized, "\u{e9}");
+        assert_eq!(type_into(mock_request()), "e\u{301}");
+    }
+
+    #[test]
+    fn command_policy_holds_input_until_enter() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let session_id = manager
+            .spawn(Arc::new(RecordedEvents::default()), mock_request())
+            .unwrap();
+        let pty = backend.take_spawned().unwrap();
+        let policy = CommandPolicy {
+            mode: PolicyMode::Denylist,
+            patterns: vec!["rm -rf".to_string()],
+        };
+
+        let write = |data: &[u8]| manager.write_checked(&session_id, data, &policy).unwrap();
+        assert_eq!(write(b"ls"), None);
+        assert!(pty.input.lock().is_empty());
+        assert_eq!(write(b" -l\r"), None);
+        assert_eq!(pty.input.lock().as_slice(), b"ls -l\r");
+
+        let blocked = write(b"rm -rf x\rls\r").unwrap();
+        assert_eq!(blocked.0, "rm -rf x");
+        assert_eq!(pty.input.lock().as_slice(), b"ls -l\r");
+
+        assert_eq!(write(b"rm -rf y\x03"), None);
+        assert_eq!(pty.input.lock().as_slice(), b"ls -l\r\x03");
+    }
+
+    #[test]
+    fn command_policy_blocks_lines_it_cant_check() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let session_id = manager
+            .spawn(Arc::new(RecordedEvents::default()), mock_request())
+            .unwrap();
+        let pty = backend.take_spawned().unwrap();
+        let policy = CommandPolicy {
+            mode: PolicyMode::Denylist,
+            patterns: vec!["rm -rf".to_string()],
+        };
+        let write = |data: &[u8]| manager.write_checked(&session_id, data, &policy).unwrap();
+
+        // Recalling history with the up arrow or Ctrl-R.
+        assert!(write(b"\x1b[A\r").is_some());
+        assert!(write(b"\x12rm\r").is_some());
+        // Editing with the cursor keys.
+        assert!(write(b"rm -f\x1b[Dr\r").is_some());
+        // Typing something and erasing it.
+        assert!(write(b"x\x7f\r").is_some());
+        assert!(pty.input.lock().is_empty());
+
+        // A denied command split with a continuation is held, then dropped.
+        assert_eq!(write(b"rm -rf \\\r"), None);
+        assert!(pty.input.lock().is_empty());
+        assert_eq!(write(b"/\r").unwrap().0, "rm -rf /");
+        assert!(pty.input.lock().is_empty());
+
+        // An allowed one is sent whole once complete.
+        assert_eq!(write(b"ls \\\r-l\r"), None);
+        assert_eq!(pty.input.lock().as_slice(), b"ls \\\r-l\r");
+    }
+
+    #[test]
+    fn detached_sessions_emit_kept_output_on_attach() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let events = Arc::new(RecordedEvents::default());
+        let session_id = manager
+            .spawn(
+                events.clone(),
+                SpawnRequest {
+                    scrollback_bytes: Some(8),
+                    ..mock_request()
+                },
+            )
+            .unwrap();
+        let pty = backend.take_spawned().unwrap();
+        let output = manager
+            .get_session(&session_id)
+            .unwrap()
+            .lock()
+            .output
+            .clone();
+
+        assert!(output.detach(8));
+        assert!(!output.detach(8));
+        pty.output.send(b"abcd".to_vec()).unwrap();
+        pty.output.send(b"efghij".to_vec()).unwrap();
+        assert!(wait_until(|| output.scrollback.lock().total == 10));
+        assert_eq!(events.count(|e| e.starts_with("output:")), 0);
+
+        assert!(output.attach());
+        assert!(!output.attach());
+        assert!(wait_until(|| events.count(|e| e == "output:cdefghij") == 1));
+        pty.output.send(b"k".to_vec()).unwrap();
+        assert!(wait_until(|| events.count(|e| e == "output:k") == 1));
+        assert_eq!(events.count(|e| e.starts_with("output:")), 2);
+    }
+
+    #[test]
+    fn no_audit_sessions_are_not_audited() {
+        let manager = PtyManager::with_backend(Arc::new(MockBackend::default()));
+        let events = Arc::new(RecordedEvents::default());
+        let audited = manager.spawn(events.clone(), mock_request()).unwrap();
+        let scratch = manager
+            .spawn(
+                events,
+                SpawnRequest {
+                    audited: false,
+                    ..mock_request()
+                },
+            )
+            .unwrap();
+
+        assert!(manager.is_audited(&audited));
+        assert!(!manager.is_audited(&scratch));
+        assert!(manager.is_audited("missing"));
+    }
+
+    #[test]
+    fn describes_spawned_sessions() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let session_id = manager
+            .spawn(Arc::new(RecordedEvents::default()), mock_request())
+            .unwrap();
+        let _pty = backend.take_spawned().unwrap();
+
+        let spawned = manager.spawned_session(&session_id).unwrap();
+        assert_eq!(spawned.session_id, session_id);
+        assert_eq!(spawned.shell, "/bin/sh");
+        assert_eq!(spawned.cwd, home_dir_string());
+        assert_eq!((spawned.rows, spawned.cols), (24, 80));
+        assert!(manager.spawned_session("missing").is_err());
+    }
+
+    #[test]
+    fn kill_forgets_the_session() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+
+        let session_id = manager
+            .spawn(Arc::new(RecordedEvents::default()), mock_request())
+            .unwrap();
+        manager.kill(&session_id, DEFAULT_KILL_GRACE).unwrap();
+
+        assert_eq!(
+            manager.kill(&session_id, DEFAULT_KILL_GRACE),
+            Err(CommandError::session_not_found(&session_id))
+        );
+        assert!(manager.write(&session_id, b"x").is_err());
+    }
+
+    #[test]
+    fn kill_stops_a_reader_waiting_for_output() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let events = Arc::new(RecordedEvents::default());
+
+        let session_id = manager.spawn(events.clone(), mock_request()).unwrap();
+        // Keep the output stream open, as a lingering child would.
+        let pty = backend.take_spawned().unwrap();
+        let output = manager
+            .get_session(&session_id)
+            .unwrap()
+            .lock()
+            .output
+            .clone();
+        manager.kill(&session_id, DEFAULT_KILL_GRACE).unwrap();
+
+        assert!(wait_until(|| !output
+            .reader_running
+            .load(Ordering::Acquire)));
+        // The shell itself exits on the signal.
+        pty.exit.send(143).unwrap();
+        assert!(wait_until(|| events.count(|e| e == "exit:Some(143)") == 1));
+    }
+
+    #[test]
+    fn layers_default_and_per_spawn_environment() {
+        let defaults = HashMap::from([
+            ("HTTPS_PROXY".to_string(), "http://proxy:3128".to_string()),
+            ("EDITOR".to_string(), "vi".to_string()),
+            ("TERM".to_string(), "dumb".to_string()),
+        ]);
+        let env_vars = HashMap::from([
+            ("EDITOR".to_string(), "nano".to_string()),
+            ("COLORTERM".to_string(), "no".to_string()),
+        ]);
+        let mut cmd = CommandBuilder::new("sh");
+        apply_env(&mut cmd, true, &defaults, env_vars);
+
+        let env = |key: &str| {
+            cmd.get_env(key)
+                .map(|value| value.to_string_lossy().to_string())
+        };
+        assert_eq!(env("HTTPS_PROXY").as_deref(), Some("http://proxy:3128"));
+        assert_eq!(env("EDITOR").as_deref(), Some("nano"));
+        assert_eq!(env("TERM").as_deref(), Some(TERM));
+        assert_eq!(env("COLORTERM").as_deref(), Some(COLORTERM));
+    }
+
+    #[test]
+    fn types_startup_commands_at_the_first_prompt() {
+        let backend = Arc::new(MockBackend::default());
+        let manager = PtyManager::with_backend(backend.clone());
+        let session_id = manager
+            .spawn(Arc::new(RecordedEvents::default()), mock_request())
+            .unwrap();
+        let pty = backend.take_spawned().unwrap();
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let log = ran.clone();
+        let on_run: Arc<dyn Fn(&str) + Send + Sync> =
+            Arc::new(move |command: &str| log.lock().push(command.to_string()));
+
+        assert!(manager
+            .queue_startup_commands(&session_id, vec!["a\nb".into()], false, on_run.clone())
+            .is_err());
+        let queue = |commands: &[&str], allow_late| {
+            manager.queue_startup_commands(
+                &session_id,
+                commands.iter().map(|c| c.to_string()).collect(),
+                allow_late,
+                on_run.clone(),
+            )
+        };
+        queue(&["source venv/bin/activate"], false).unwrap();
+        queue(&["cd src"], false).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(pty.input.lock().is_empty());
+
+        pty.output.send(b"\x1b]133;A\x07$ ".to_vec()).unwrap();
+        assert!(wait_until(|| ran.lock().len() == 2));
+        assert_eq!(
+            pty.input.lock().as_slice(),
+            b"source venv/bin/activate\ncd src\n"
+        );
+
+        assert!(matches!(
+            queue(&["ls"], false),
+            Err(CommandError::InvalidState(_))
+        ));
+        queue(&["ls"], true).unwrap();
+        assert!(wait_until(|| ran.lock().len() == 3));
+        assert!(pty.input.lock().ends_with(b"ls\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn prefers_the_real_cwd_over_a_conflicting_report() {
+        let real = std::env::temp_dir().canonicalize().unwrap();
+        let real = real.to_string_lossy().to_string();
+        let other = "/".to_string();
+        let fallback = || "/fallback".to_string();
+
+        assert_eq!(
+            choose_cwd(Some(real.clone()), Some(real.clone()), fallback()),
+            real
+        );
+        assert_eq!(
+            choose_cwd(Some(other.clone()), Some(real.clone()), fallback()),
+            real
+        );
+        assert_eq!(choose_cwd(Some(other.clone()), None, fallback()), other);
+        assert_eq!(choose_cwd(None, None, fallback()), fallback());
+    }
+
+    #[test]
+    fn splits_batched_lsof_output_by_pid() {
+        let stdout = "p101\nfcwd\nn/Users/me/project\np202\nfcwd\nn/tmp\np303\n";
+        let cwds = parse_lsof_cwds(stdout);
+        assert_eq!(cwds.len(), 2);
+        assert_eq!(cwds[&101], "/Users/me/project");
+        assert_eq!(cwds[&202], "/tmp");
+    }
+
+    #[test]
+    fn accepts_only_plain_tmux_session_names() {
+        for name in ["work", "ai-terminal_2", "A1"] {
+            assert!(is_valid_tmux_session_name(name), "{}", name);
+        }
+        for name in ["", "-t", "a:b", "a.b", "=x", "a b", "a;rm", &"x".repeat(65)] {
+            assert!(!is_valid_tmux_session_name(name), "{}", name);
+        }
+    }
 }
@@ -0,0 +1,375 @@
+use portable_pty::{native_pty_system, Child, CommandBuilder, ExitStatus, MasterPty, PtySize};
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use crate::error::CommandError;
+
+/// Opens PTYs and starts programs in them. `PtyManager` holds one so tests
+/// can run the session lifecycle without a real terminal.
+pub trait PtyBackend: Send + Sync {
+    fn spawn(&self, size: PtySize, cmd: CommandBuilder) -> Result<SpawnedPty, CommandError>;
+}
+
+/// A program running under a freshly opened PTY.
+pub struct SpawnedPty {
+    pub master: Box<dyn PtyMaster>,
+    pub reader: Box<dyn Read + Send>,
+    pub writer: Box<dyn Write + Send>,
+    pub child: Box<dyn PtyChild>,
+}
+
+/// Controller side of a PTY, kept by the session after spawn.
+pub trait PtyMaster: Send {
+    fn resize(&self, size: PtySize) -> Result<(), CommandError>;
+    /// Process group in the foreground of the terminal, if known.
+    fn foreground_pid(&self) -> Option<u32>;
+    /// Path of the terminal device the program sees, if known.
+    fn tty_name(&self) -> Option<std::path::PathBuf>;
+}
+
+/// The program started in a PTY.
+pub trait PtyChild: Send {
+    fn process_id(&self) -> Option<u32>;
+    fn wait(&mut self) -> std::io::Result<ExitStatus>;
+}
+
+/// How often a non-blocking reader wakes without output by default.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The platform PTY from `portable_pty::native_pty_system`.
+#[derive(Debug, Clone, Copy)]
+pub struct NativeBackend {
+    /// On Unix, put the master in non-blocking mode and have reads give up
+    /// with `ErrorKind::TimedOut` after this long without output, so the
+    /// session's reader thread can check whether it should stop. `None`
+    /// uses plain blocking reads. Ignored elsewhere.
+    pub poll_interval: Option<Duration>,
+}
+
+impl Default for NativeBackend {
+    fn default() -> Self {
+        Self {
+            poll_interval: cfg!(unix).then_some(DEFAULT_POLL_INTERVAL),
+        }
+    }
+}
+
+impl PtyBackend for NativeBackend {
+    fn spawn(&self, size: PtySize, cmd: CommandBuilder) -> Result<SpawnedPty, CommandError> {
+        let program = cmd
+            .get_argv()
+            .first()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let pair = native_pty_system()
+            .openpty(size)
+            .map_err(|e| CommandError::Spawn(format!("Failed to open PTY: {}", e)))?;
+
+        let child = pair
+            .slave
+            .spawn_command(cmd)
+            .map_err(|e| CommandError::Spawn(format!("Failed to spawn {}: {}", program, e)))?;
+
+        let writer = pair
+            .master
+            .take_writer()
+            .map_err(|e| CommandError::Io(format!("Failed to get PTY writer: {}", e)))?;
+
+        let reader = pair
+            .master
+            .try_clone_reader()
+            .map_err(|e| CommandError::Io(format!("Failed to get PTY reader: {}", e)))?;
+
+        #[cfg(unix)]
+        let (reader, writer) = match (self.poll_interval, pair.master.as_raw_fd()) {
+            (Some(interval), Some(fd)) => nonblocking::wrap(fd, reader, writer, interval)
+                .map_err(|e| CommandError::io("Failed to make PTY non-blocking", e))?,
+            _ => (reader, writer),
+        };
+
+        Ok(SpawnedPty {
+            master: Box::new(pair.master),
+            reader,
+            writer,
+            child: Box::new(child),
+        })
+    }
+}
+
+impl PtyMaster for Box<dyn MasterPty + Send> {
+    fn resize(&self, size: PtySize) -> Result<(), CommandError> {
+        MasterPty::resize(self.as_ref(), size)
+            .map_err(|e| CommandError::Io(format!("Failed to resize PTY: {}", e)))
+    }
+
+    #[cfg(unix)]
+    fn foreground_pid(&self) -> Option<u32> {
+        // `tcgetpgrp` on the master fd.
+        MasterPty::process_group_leader(self.as_ref()).and_then(|pid| u32::try_from(pid).ok())
+    }
+
+    #[cfg(not(unix))]
+    fn foreground_pid(&self) -> Option<u32> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn tty_name(&self) -> Option<std::path::PathBuf> {
+        MasterPty::tty_name(self.as_ref())
+    }
+
+    #[cfg(not(unix))]
+    fn tty_name(&self) -> Option<std::path::PathBuf> {
+        None
+    }
+}
+
+impl PtyChild for Box<dyn Child + Send + Sync> {
+    fn process_id(&self) -> Option<u32> {
+        Child::process_id(self.as_ref())
+    }
+
+    fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        Child::wait(self.as_mut())
+    }
+}
+
+/// Non-blocking I/O on a PTY master, waiting with `poll` instead.
+#[cfg(unix)]
+mod nonblocking {
+    use std::io::{self, Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Set `O_NONBLOCK` on the master's open file, which its reader and
+    /// writer share, and wrap them so callers still see a blocking writer
+    /// and a reader that times out after `interval`.
+    pub fn wrap(
+        master_fd: RawFd,
+        reader: Box<dyn Read + Send>,
+        writer: Box<dyn Write + Send>,
+        interval: Duration,
+    ) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        // Our own descriptor to poll, valid for as long as the wrappers live.
+        let fd = unsafe { libc::dup(master_fd) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fd) });
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0
+            || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((
+            Box::new(PollingReader {
+                inner: reader,
+                fd: fd.clone(),
+                interval,
+            }),
+            Box::new(PollingWriter { inner: writer, fd }),
+        ))
+    }
+
+    /// Wait until `fd` has one of `events`, returning `false` on timeout.
+    fn poll(fd: &OwnedFd, events: libc::c_short, timeout: Option<Duration>) -> io::Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: fd.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+            0 => Ok(false),
+            n if n > 0 => Ok(true),
+            _ => {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(false),
+                    _ => Err(e),
+                }
+            }
+        }
+    }
+
+    struct PollingReader {
+        inner: Box<dyn Read + Send>,
+        fd: Arc<OwnedFd>,
+        interval: Duration,
+    }
+
+    impl Read for PollingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                match self.inner.read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        if !poll(&self.fd, libc::POLLIN, Some(self.interval))? {
+                            return Err(io::ErrorKind::TimedOut.into());
+                        }
+                    }
+                    result => return result,
+                }
+            }
+        }
+    }
+
+    struct PollingWriter {
+        inner: Box<dyn Write + Send>,
+        fd: Arc<OwnedFd>,
+    }
+
+    impl Write for PollingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            loop {
+                match self.inner.write(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        poll(&self.fd, libc::POLLOUT, None)?;
+                    }
+                    result => return result,
+                }
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            loop {
+                match self.inner.flush() {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        poll(&self.fd, libc::POLLOUT, None)?;
+                    }
+                    result => return result,
+                }
+            }
+        }
+    }
+}
+
+/// In-memory backend whose sessions are driven by the test through
+/// `MockPty` handles.
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::{mpsc, Arc};
+
+    /// Test-side handle to one spawned mock session.
+    pub struct MockPty {
+        /// Bytes sent here are read by the session as program output.
+        /// Dropping it ends the output stream.
+        pub output: mpsc::Sender<Vec<u8>>,
+        /// Exit code for the program; dropping it makes `wait` fail.
+        pub exit: mpsc::Sender<u32>,
+        /// Everything written to the session's PTY.
+        pub input: Arc<Mutex<Vec<u8>>>,
+        /// Last size passed to `resize`.
+        pub size: Arc<Mutex<PtySize>>,
+    }
+
+    #[derive(Default)]
+    pub struct MockBackend {
+        spawned: Mutex<Vec<MockPty>>,
+    }
+
+    impl MockBackend {
+        /// Take the handle for the earliest spawn not yet taken.
+        pub fn take_spawned(&self) -> Option<MockPty> {
+            let mut spawned = self.spawned.lock();
+            (!spawned.is_empty()).then(|| spawned.remove(0))
+        }
+    }
+
+    impl PtyBackend for MockBackend {
+        fn spawn(&self, size: PtySize, _cmd: CommandBuilder) -> Result<SpawnedPty, CommandError> {
+            let (output_tx, output_rx) = mpsc::channel();
+            let (exit_tx, exit_rx) = mpsc::channel();
+            let input = Arc::new(Mutex::new(Vec::new()));
+            let size = Arc::new(Mutex::new(size));
+
+            self.spawned.lock().push(MockPty {
+                output: output_tx,
+                exit: exit_tx,
+                input: input.clone(),
+                size: size.clone(),
+            });
+            Ok(SpawnedPty {
+                master: Box::new(MockMaster(size)),
+                reader: Box::new(ChannelReader {
+                    rx: output_rx,
+                    pending: Vec::new(),
+                }),
+                writer: Box::new(SharedWriter(input)),
+                child: Box::new(MockChild(exit_rx)),
+            })
+        }
+    }
+
+    struct MockMaster(Arc<Mutex<PtySize>>);
+
+    impl PtyMaster for MockMaster {
+        fn resize(&self, size: PtySize) -> Result<(), CommandError> {
+            *self.0.lock() = size;
+            Ok(())
+        }
+
+        fn foreground_pid(&self) -> Option<u32> {
+            None
+        }
+
+        fn tty_name(&self) -> Option<std::path::PathBuf> {
+            None
+        }
+    }
+
+    struct MockChild(mpsc::Receiver<u32>);
+
+    impl PtyChild for MockChild {
+        fn process_id(&self) -> Option<u32> {
+            None
+        }
+
+        fn wait(&mut self) -> std::io::Result<ExitStatus> {
+            self.0
+                .recv()
+                .map(ExitStatus::with_exit_code)
+                .map_err(|_| std::io::Error::other("mock program was dropped"))
+        }
+    }
+
+    struct ChannelReader {
+        rx: mpsc::Receiver<Vec<u8>>,
+        pending: Vec<u8>,
+    }
+
+    impl Read for ChannelReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pending.is_empty() {
+                // Times out like `NativeBackend` with a poll interval.
+                match self.rx.recv_timeout(Duration::from_millis(20)) {
+                    Ok(chunk) => self.pending = chunk,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        return Err(std::io::ErrorKind::TimedOut.into());
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+                }
+            }
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            Ok(n)
+        }
+    }
+
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::pty::Utf8Decoder;
+
+/// Writes PTY output to an asciinema v2 `.cast` file.
+///
+/// Format: one JSON header line, then `[elapsed_secs, "o", data]` output
+/// events and `[elapsed_secs, "r", "COLSxROWS"]` resize events.
+pub struct CastRecorder {
+    writer: BufWriter<File>,
+    started: Instant,
+    decoder: Utf8Decoder,
+}
+
+impl CastRecorder {
+    /// Create the cast file and write its header. Fails if `path` exists,
+    /// so a recording never overwrites another file.
+    pub fn create(path: &Path, cols: u16, rows: u16, shell: &str) -> Result<Self, String> {
+        let mut options = OpenOptions::new();
+        options.create_new(true).write(true);
+
+        #[cfg(unix)]
+        {
+            options.mode(0o600);
+        }
+
+        let file = options.open(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => {
+                format!("Recording file already exists: {}", path.display())
+            }
+            _ => format!("Failed to create recording file: {}", e),
+        })?;
+
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": chrono::Utc::now().timestamp(),
+            "env": {
+                "SHELL": shell,
+                "TERM": "xterm-256color",
+            },
+        });
+
+        let mut recorder = Self {
+            writer: BufWriter::new(file),
+            started: Instant::now(),
+            decoder: Utf8Decoder::default(),
+        };
+        recorder.write_line(&header)?;
+        Ok(recorder)
+    }
+
+    /// Append an output event.
+    pub fn output(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let data = self.decoder.decode(bytes);
+        if data.is_empty() {
+            return Ok(());
+        }
+        let event = serde_json::json!([self.elapsed(), "o", data]);
+        self.write_line(&event)
+    }
+
+    /// Append a resize event.
+    pub fn resize(&mut self, cols: u16, rows: u16) -> Result<(), String> {
+        let event = serde_json::json!([self.elapsed(), "r", format!("{}x{}", cols, rows)]);
+        self.write_line(&event)
+    }
+
+    /// Flush any remaining events to disk.
+    pub fn finish(mut self) -> Result<(), String> {
+        let data = self.decoder.finish();
+        if !data.is_empty() {
+            let event = serde_json::json!([self.elapsed(), "o", data]);
+            self.write_line(&event)?;
+        }
+        self.writer
+            .flush()
+            .map_err(|e| format!("Failed to flush recording: {}", e))
+    }
+
+    fn elapsed(&self) -> f64 {
+        // Millisecond precision is plenty for playback.
+        (self.started.elapsed().as_secs_f64() * 1000.0).round() / 1000.0
+    }
+
+    fn write_line(&mut self, value: &serde_json::Value) -> Result<(), String> {
+        serde_json::to_writer(&mut self.writer, value)
+            .map_err(|e| format!("Failed to write recording: {}", e))?;
+        self.writer
+            .write_all(b"\n")
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| format!("Failed to write recording: {}", e))
+    }
+}
+
+/// One event of a `.cast` file: seconds since the start, the event type
+/// (`"o"` output, `"r"` resize, ...) and its data.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct CastEvent(pub f64, pub String, pub String);
+
+/// Read an asciinema v2 `.cast` file for playback.
+pub fn read_cast(path: &Path) -> Result<Vec<CastEvent>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read recording {}: {}", path.display(), e))?;
+    parse_cast(&contents)
+}
+
+/// Parse `.cast` contents, checking the header's version and that event
+/// times never go backwards.
+pub fn parse_cast(contents: &str) -> Result<Vec<CastEvent>, String> {
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let header: serde_json::Value = lines
+        .next()
+        .ok_or("Recording is empty")
+        .and_then(|line| serde_json::from_str(line).map_err(|_| "Invalid recording header"))?;
+    if header.get("version").and_then(|v| v.as_u64()) != Some(2) {
+        return Err("Only asciinema v2 recordings are supported".to_string());
+    }
+
+    let mut events: Vec<CastEvent> = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let event: CastEvent = serde_json::from_str(line)
+            .map_err(|e| format!("Invalid recording event {}: {}", i + 1, e))?;
+        let previous = events.last().map_or(0.0, |last| last.0);
+        if !event.0.is_finite() || event.0 < previous {
+            return Err(format!("Recording event {} is out of order", i + 1));
+        }
+        events.push(event);
+    }
+    Ok(events)
+}
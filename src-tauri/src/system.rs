@@ -0,0 +1,612 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::process::output_with_timeout;
+
+/// Runtime OS details. Fields are `None` when detection fails.
+#[derive(Debug, Clone, Default)]
+pub struct OsDetails {
+    pub name: String,
+    pub version: Option<String>,
+    pub kernel: Option<String>,
+    /// Linux distribution name; always `None` on macOS.
+    pub distro: Option<String>,
+    pub arch: String,
+}
+
+/// Memory figures in bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryDetails {
+    pub total: Option<u64>,
+    pub available: Option<u64>,
+}
+
+pub fn os_details() -> OsDetails {
+    let uname = uname();
+    let machine = uname
+        .as_ref()
+        .map(|u| u.machine.clone())
+        .unwrap_or_else(|| std::env::consts::ARCH.to_string());
+
+    let mut details = OsDetails {
+        name: match std::env::consts::OS {
+            "macos" => "macOS".to_string(),
+            "linux" => "Linux".to_string(),
+            "windows" => "Windows".to_string(),
+            other => other.to_string(),
+        },
+        kernel: uname.map(|u| u.release),
+        arch: normalize_arch(&machine),
+        ..OsDetails::default()
+    };
+
+    #[cfg(target_os = "macos")]
+    {
+        details.version = command_stdout("sw_vers", &["-productVersion"]);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        if let Ok(contents) = std::fs::read_to_string("/etc/os-release") {
+            let release = parse_os_release(&contents);
+            details.distro = release.get("NAME").or_else(|| release.get("ID")).cloned();
+            details.version = release.get("VERSION_ID").cloned();
+        }
+    }
+
+    details
+}
+
+pub fn memory_details() -> MemoryDetails {
+    #[cfg(target_os = "linux")]
+    {
+        let Ok(contents) = std::fs::read_to_string("/proc/meminfo") else {
+            return MemoryDetails::default();
+        };
+        let kb = |key: &str| -> Option<u64> {
+            contents
+                .lines()
+                .find_map(|l| l.strip_prefix(key))
+                .and_then(|rest| rest.trim_start_matches(':').split_whitespace().next())
+                .and_then(|n| n.parse::<u64>().ok())
+                .map(|n| n * 1024)
+        };
+        MemoryDetails {
+            total: kb("MemTotal"),
+            available: kb("MemAvailable"),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let total = command_stdout("sysctl", &["-n", "hw.memsize"]).and_then(|s| s.parse().ok());
+        let available = command_stdout("vm_stat", &[]).and_then(|s| parse_vm_stat_available(&s));
+        MemoryDetails { total, available }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        MemoryDetails::default()
+    }
+}
+
+pub fn cpu_cores() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Usage of one mounted filesystem, from `df`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiskUsage {
+    pub filesystem: String,
+    pub mount_point: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub available_bytes: u64,
+    pub capacity_percent: u8,
+}
+
+/// Usage of every mounted filesystem, via `df -kP`.
+pub fn disk_usage() -> std::io::Result<Vec<DiskUsage>> {
+    let output = output_with_timeout(Command::new("df").arg("-kP"), Duration::from_secs(2))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::TimedOut, "df timed out"))?;
+    Ok(parse_df_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse POSIX `df -kP` output. Filesystem names and mount points may
+/// contain spaces, so rows are split around the four numeric columns.
+fn parse_df_output(output: &str) -> Vec<DiskUsage> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let start = (1..words.len().saturating_sub(4)).find(|&i| {
+                words[i..i + 3].iter().all(|w| w.parse::<u64>().is_ok())
+                    && words[i + 3].ends_with('%')
+            })?;
+            let kb = |w: &str| w.parse::<u64>().ok().map(|n| n * 1024);
+            Some(DiskUsage {
+                filesystem: words[..start].join(" "),
+                mount_point: words[start + 4..].join(" "),
+                total_bytes: kb(words[start])?,
+                used_bytes: kb(words[start + 1])?,
+                available_bytes: kb(words[start + 2])?,
+                capacity_percent: words[start + 3].trim_end_matches('%').parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Space on the filesystem holding a path, from `free_space`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FreeSpace {
+    /// The path measured: the one asked for, or its nearest existing
+    /// ancestor if it doesn't exist yet.
+    pub path: String,
+    pub total_bytes: u64,
+    /// Free bytes, including any reserved for the superuser.
+    pub free_bytes: u64,
+    /// Free bytes this user can write.
+    pub available_bytes: u64,
+}
+
+/// Space on the filesystem that holds (or would hold) `path`, queried
+/// directly rather than through `df`.
+pub fn free_space(path: &Path) -> std::io::Result<FreeSpace> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No existing ancestor"))?;
+    let (total_bytes, free_bytes, available_bytes) = filesystem_space(existing)?;
+    Ok(FreeSpace {
+        path: existing.to_string_lossy().to_string(),
+        total_bytes,
+        free_bytes,
+        available_bytes,
+    })
+}
+
+/// Total, free and available bytes via `statvfs`.
+#[cfg(unix)]
+// The field types differ by platform and are already `u64` on some.
+#[allow(clippy::unnecessary_cast)]
+fn filesystem_space(path: &Path) -> std::io::Result<(u64, u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let unit = stat.f_frsize as u64;
+    let bytes = |blocks| unit.saturating_mul(blocks);
+    Ok((
+        bytes(stat.f_blocks as u64),
+        bytes(stat.f_bfree as u64),
+        bytes(stat.f_bavail as u64),
+    ))
+}
+
+/// Total, free and available bytes via `GetDiskFreeSpaceExW`.
+#[cfg(windows)]
+fn filesystem_space(path: &Path) -> std::io::Result<(u64, u64, u64)> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let (mut available, mut total, mut free) = (0u64, 0u64, 0u64);
+    if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, &mut total, &mut free) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((total, free, available))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn filesystem_space(_path: &Path) -> std::io::Result<(u64, u64, u64)> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Free space is not available on this platform",
+    ))
+}
+
+/// One entry of `describe_dir_entry`, roughly a line of `ls -la`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DetailedEntry {
+    pub name: String,
+    /// `"file"`, `"dir"`, `"symlink"` or `"other"`.
+    pub kind: &'static str,
+    pub size: u64,
+    /// `ls`-style permission bits, e.g. `rwxr-xr-x`; `None` off Unix.
+    pub permissions: Option<String>,
+    pub modified: Option<DateTime<Utc>>,
+    pub symlink_target: Option<String>,
+}
+
+/// Describe `path` without following symlinks.
+pub fn describe_dir_entry(name: String, path: &Path) -> std::io::Result<DetailedEntry> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "other"
+    };
+
+    #[cfg(unix)]
+    let permissions = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(permission_string(metadata.permissions().mode()))
+    };
+    #[cfg(not(unix))]
+    let permissions = None;
+
+    Ok(DetailedEntry {
+        name,
+        kind,
+        size: metadata.len(),
+        permissions,
+        modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+        symlink_target: file_type
+            .is_symlink()
+            .then(|| std::fs::read_link(path).ok())
+            .flatten()
+            .map(|target| target.to_string_lossy().to_string()),
+    })
+}
+
+/// What `snapshot_dir` records about one entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+    pub is_dir: bool,
+}
+
+/// Entries of a directory by name, from `snapshot_dir`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DirectorySnapshot {
+    pub entries: HashMap<String, SnapshotEntry>,
+    /// The directory had more than `max_entries` entries.
+    pub truncated: bool,
+}
+
+/// Record the size, modification time and type of up to `max_entries`
+/// entries of `dir`, without following symlinks. Dotfiles are skipped
+/// unless `show_hidden` is set.
+pub fn snapshot_dir(
+    dir: &Path,
+    show_hidden: bool,
+    max_entries: usize,
+) -> std::io::Result<DirectorySnapshot> {
+    let mut snapshot = DirectorySnapshot::default();
+    for (scanned, entry) in std::fs::read_dir(dir)?.flatten().enumerate() {
+        if scanned >= max_entries {
+            snapshot.truncated = true;
+            break;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !show_hidden && name.starts_with('.') {
+            continue;
+        }
+        // Entries can vanish between listing and stat; skip them.
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        snapshot.entries.insert(
+            name,
+            SnapshotEntry {
+                size: metadata.len(),
+                modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+                is_dir: metadata.is_dir(),
+            },
+        );
+    }
+    Ok(snapshot)
+}
+
+/// Entry names that changed between two snapshots, each sorted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SnapshotDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Present in both with a different size, modification time or type.
+    pub modified: Vec<String>,
+}
+
+/// Compare two snapshots' entries by name.
+pub fn diff_snapshots(
+    previous: &HashMap<String, SnapshotEntry>,
+    current: &HashMap<String, SnapshotEntry>,
+) -> SnapshotDiff {
+    let mut diff = SnapshotDiff::default();
+    for (name, entry) in current {
+        match previous.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(old) if old != entry => diff.modified.push(name.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = previous
+        .keys()
+        .filter(|name| !current.contains_key(*name))
+        .cloned()
+        .collect();
+    diff.added.sort();
+    diff.removed.sort();
+    diff.modified.sort();
+    diff
+}
+
+/// Bytes checked for NUL bytes when deciding whether a file is binary.
+const BINARY_SNIFF_BYTES: usize = 8192;
+
+/// The start of a file, from `read_text_file`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileContents {
+    /// Empty for binary files.
+    pub content: String,
+    /// The file continues past `content`.
+    pub truncated: bool,
+    /// A NUL byte appeared near the start of the file.
+    pub is_binary: bool,
+}
+
+/// Read up to `max_bytes` of a text file, cutting on a character boundary.
+/// Files with a NUL byte in their first 8 KiB are reported as binary and
+/// their content isn't returned. Invalid UTF-8 is replaced.
+pub fn read_text_file(path: &Path, max_bytes: usize) -> std::io::Result<FileContents> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?
+        .take(max_bytes.max(BINARY_SNIFF_BYTES) as u64 + 1)
+        .read_to_end(&mut bytes)?;
+
+    if bytes.iter().take(BINARY_SNIFF_BYTES).any(|&b| b == 0) {
+        return Ok(FileContents {
+            content: String::new(),
+            truncated: false,
+            is_binary: true,
+        });
+    }
+
+    let truncated = bytes.len() > max_bytes;
+    if truncated {
+        bytes.truncate(max_bytes);
+        // Drop a character split by the cut rather than replacing it.
+        if let Err(e) = std::str::from_utf8(&bytes) {
+            if e.error_len().is_none() {
+                bytes.truncate(e.valid_up_to());
+            }
+        }
+    }
+    Ok(FileContents {
+        content: String::from_utf8_lossy(&bytes).into_owned(),
+        truncated,
+        is_binary: false,
+    })
+}
+
+/// Render the low nine mode bits like `ls` does, e.g. `rw-r--r--`.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn permission_string(mode: u32) -> String {
+    (0..9)
+        .map(|bit| {
+            if mode & (0o400 >> bit) == 0 {
+                '-'
+            } else {
+                ['r', 'w', 'x'][bit % 3]
+            }
+        })
+        .collect()
+}
+
+/// Report Apple Silicon and 64-bit ARM Linux consistently as `arm64`.
+fn normalize_arch(machine: &str) -> String {
+    match machine {
+        "aarch64" | "arm64" => "arm64".to_string(),
+        "amd64" | "x86_64" => "x86_64".to_string(),
+        other => other.to_string(),
+    }
+}
+
+struct Uname {
+    release: String,
+    machine: String,
+}
+
+#[cfg(unix)]
+fn uname() -> Option<Uname> {
+    let mut info: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut info) } != 0 {
+        return None;
+    }
+    let field = |raw: &[libc::c_char]| {
+        let bytes: Vec<u8> = raw
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        String::from_utf8_lossy(&bytes).to_string()
+    };
+    Some(Uname {
+        release: field(&info.release),
+        machine: field(&info.machine),
+    })
+}
+
+#[cfg(not(unix))]
+fn uname() -> Option<Uname> {
+    None
+}
+
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn command_stdout(program: &str, args: &[&str]) -> Option<String> {
+    let output =
+        output_with_timeout(Command::new(program).args(args), Duration::from_secs(2)).ok()??;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Parse `KEY=value` lines from `/etc/os-release`, unquoting values.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_os_release(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| {
+            let v = v.trim().trim_matches('"').trim_matches('\'');
+            (k.trim().to_string(), v.to_string())
+        })
+        .collect()
+}
+
+/// Estimate available memory from `vm_stat` as free + inactive + speculative pages.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_vm_stat_available(output: &str) -> Option<u64> {
+    let page_size: u64 = output
+        .lines()
+        .next()?
+        .split("page size of ")
+        .nth(1)?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+
+    let pages = |key: &str| -> u64 {
+        output
+            .lines()
+            .find_map(|l| l.strip_prefix(key))
+            .and_then(|rest| rest.trim().trim_end_matches('.').parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+
+    let free = pages("Pages free:") + pages("Pages inactive:") + pages("Pages speculative:");
+    Some(free * page_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_df_rows_with_spaces() {
+        let output = "Filesystem 1024-blocks Used Available Capacity Mounted on\n\
+                      /dev/disk3s1 971350180 500000000 400000000 56% /\n\
+                      map auto_home 0 0 0 100% /System/Volumes/Data/home\n\
+                      /dev/disk4s1 1000 10 990 1% /Volumes/My Drive\n";
+        let disks = parse_df_output(output);
+        assert_eq!(disks.len(), 3);
+        assert_eq!(disks[0].mount_point, "/");
+        assert_eq!(disks[0].total_bytes, 971350180 * 1024);
+        assert_eq!(disks[0].capacity_percent, 56);
+        assert_eq!(disks[1].filesystem, "map auto_home");
+        assert_eq!(disks[2].mount_point, "/Volumes/My Drive");
+    }
+
+    #[test]
+    fn reads_text_files_and_detects_binary_ones() {
+        let path = std::env::temp_dir().join(format!("context-{}", uuid::Uuid::new_v4()));
+
+        std::fs::write(&path, "héllo").unwrap();
+        let whole = read_text_file(&path, 100).unwrap();
+        assert_eq!(whole.content, "héllo");
+        assert!(!whole.truncated && !whole.is_binary);
+
+        // The cut at 2 bytes falls inside "é".
+        let cut = read_text_file(&path, 2).unwrap();
+        assert_eq!(cut.content, "h");
+        assert!(cut.truncated);
+
+        std::fs::write(&path, b"\x7fELF\x02\x01\x00\x00").unwrap();
+        let binary = read_text_file(&path, 100).unwrap();
+        assert!(binary.is_binary);
+        assert!(binary.content.is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn diffs_directory_snapshots() {
+        let entry = |size| SnapshotEntry {
+            size,
+            modified: None,
+            is_dir: false,
+        };
+        let previous = HashMap::from([
+            ("kept".to_string(), entry(1)),
+            ("grown".to_string(), entry(1)),
+            ("deleted".to_string(), entry(1)),
+        ]);
+        let current = HashMap::from([
+            ("kept".to_string(), entry(1)),
+            ("grown".to_string(), entry(2)),
+            ("new".to_string(), entry(0)),
+        ]);
+
+        let diff = diff_snapshots(&previous, &current);
+        assert_eq!(diff.added, ["new"]);
+        assert_eq!(diff.removed, ["deleted"]);
+        assert_eq!(diff.modified, ["grown"]);
+        assert_eq!(diff_snapshots(&current, &current), SnapshotDiff::default());
+    }
+
+    #[test]
+    fn renders_permission_bits() {
+        assert_eq!(permission_string(0o755), "rwxr-xr-x");
+        assert_eq!(permission_string(0o100644), "rw-r--r--");
+        assert_eq!(permission_string(0), "---------");
+    }
+
+    #[test]
+    fn parses_os_release() {
+        let release =
+            parse_os_release("NAME=\"Ubuntu\"\nVERSION_ID=\"24.04\"\nID=ubuntu\n# comment\n");
+        assert_eq!(release.get("NAME").map(String::as_str), Some("Ubuntu"));
+        assert_eq!(release.get("VERSION_ID").map(String::as_str), Some("24.04"));
+        assert_eq!(release.get("ID").map(String::as_str), Some("ubuntu"));
+    }
+
+    #[test]
+    fn parses_vm_stat() {
+        let output = "Mach Virtual Memory Statistics: (page size of 16384 bytes)\n\
+                      Pages free:                               1000.\n\
+                      Pages active:                             5000.\n\
+                      Pages inactive:                           2000.\n\
+                      Pages speculative:                         500.\n";
+        assert_eq!(parse_vm_stat_available(output), Some(3500 * 16384));
+    }
+
+    #[test]
+    fn measures_free_space_at_the_nearest_existing_ancestor() {
+        let dir = std::env::temp_dir();
+        let space = free_space(&dir.join("not-created-yet").join("file.bin")).unwrap();
+        assert_eq!(Path::new(&space.path), dir.as_path());
+        assert!(space.total_bytes > 0);
+        assert!(space.available_bytes <= space.free_bytes);
+        assert!(space.free_bytes <= space.total_bytes);
+    }
+}
@@ -66,7 +66,14 @@ export const core = {
     // Default responses for known commands
     switch (cmd) {
       case "spawn_shell":
-        return "mock-pty-id";
+        return {
+          session_id: "mock-pty-id",
+          child_id: 4242,
+          shell: "/bin/zsh",
+          cwd: "/Users/test/projects",
+          rows: args?.rows ?? 24,
+          cols: args?.cols ?? 80,
+        };
       case "write_to_pty":
         return undefined;
       case "resize_pty":
@@ -141,7 +141,10 @@ export function useTerminal(options: UseTerminalOptions): UseTerminalReturn {
         const cols = terminal.cols;
         const rows = terminal.rows;
 
-        const sid = await invoke<string>("spawn_shell", { rows, cols });
+        const { session_id: sid } = await invoke<{ session_id: string }>("spawn_shell", {
+          rows,
+          cols,
+        });
         sessionIdRef.current = sid;
         setSessionId(sid);
         setIsConnected(true);
@@ -169,7 +172,12 @@ export function useTerminal(options: UseTerminalOptions): UseTerminalReturn {
           }
         });
       } catch (err) {
-        terminal.writeln(`\r\n\x1b[31mFailed to start shell: ${err}\x1b[0m\r\n`);
+        // PTY commands reject with `{ kind, message }`
+        const message =
+          typeof err === "object" && err !== null && "message" in err
+            ? String((err as { message: unknown }).message)
+            : String(err);
+        terminal.writeln(`\r\n\x1b[31mFailed to start shell: ${message}\x1b[0m\r\n`);
       }
     };
 
@@ -194,8 +202,17 @@ export function useTerminal(options: UseTerminalOptions): UseTerminalReturn {
     let unlisten: (() => void) | null = null;
 
     const setup = async () => {
-      unlisten = await listen<{ session_id: string; data: string }>("pty-output", (event) => {
+      unlisten = await listen<{
+        session_id: string;
+        data: string;
+        encoding?: "utf8" | "base64";
+      }>("pty-output", (event) => {
         if (event.payload.session_id === sessionIdRef.current) {
+          if (event.payload.encoding === "base64") {
+            const bytes = Uint8Array.from(atob(event.payload.data), (c) => c.charCodeAt(0));
+            terminalRef.current?.write(bytes);
+            return;
+          }
           terminalRef.current?.write(event.payload.data);
           // Record output for terminal recording
           const rm = getRecordingManager();
@@ -218,8 +235,12 @@ export function useTerminal(options: UseTerminalOptions): UseTerminalReturn {
     let unlisten: (() => void) | null = null;
 
     const setup = async () => {
-      unlisten = await listen<string>("pty-exit", (event) => {
-        if (event.payload === sessionIdRef.current) {
+      unlisten = await listen<{
+        session_id: string;
+        exit_code: number | null;
+        signal: number | null;
+      }>("pty-exit", (event) => {
+        if (event.payload.session_id === sessionIdRef.current) {
           setIsConnected(false);
           terminalRef.current?.writeln("\r\n\x1b[33m[Shell session ended]\x1b[0m\r\n");
         }
@@ -38,6 +38,7 @@ export class SafetyLogger {
   /** Get log entries from the backend. */
   async getEntries(options?: { date?: string; sessionId?: string; limit?: number }): Promise<
     Array<{
+      schema_version: number;
       id: string;
       timestamp: string;
       command: string;
@@ -46,6 +47,7 @@ export class SafetyLogger {
       approved: boolean;
       exit_code: number | null;
       output_preview: string | null;
+      output_truncated: boolean;
       session_id: string;
     }>
   > {